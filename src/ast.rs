@@ -6,19 +6,41 @@ pub enum Stmt {
     Expr(Box<Expr>),
     Print(Box<Expr>),
     Var(Token, Box<Option<Expr>>),
-    If(Box<Expr>, Box<Stmt>, Option<Box<Stmt>>),
-    While(Box<Expr>, Box<Stmt>),
+    // The keyword token is kept so runtime errors in the condition have a line.
+    If(Token, Box<Expr>, Box<Stmt>, Option<Box<Stmt>>),
+    // The last field is a desugared `for` loop's increment, which runs after
+    // every iteration of the body, including ones cut short by `continue`.
+    While(Token, Box<Expr>, Box<Stmt>, Option<Box<Expr>>),
+    // `for (name in iterable) body`.
+    ForIn(Token, Token, Box<Expr>, Box<Stmt>),
+    Function(Token, Vec<Token>, Vec<Stmt>),
+    Return(Token, Option<Expr>),
+    Break(Token),
+    Continue(Token),
+    // The superclass is an `Expr::Variable`; every method is a `Stmt::Function`.
+    Class(Token, Option<Expr>, Vec<Stmt>),
 }
+// Variable references carry an `Option<usize>` filled in by the resolver: the
+// number of environments between the reference and its declaration, or `None`
+// for a global.
 #[derive(Debug, Clone)]
 pub enum Expr {
     // Literal Values
     Literal(Literal),
 
     // Compound Expressions
-    Assignment(Token, Box<Expr>),
+    Assignment(Token, Box<Expr>, Option<usize>),
     Binary(Box<Expr>, Token, Box<Expr>),
     Logical(Box<Expr>, Token, Box<Expr>),
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
     Unary(Token, Box<Expr>),
     Grouping(Box<Expr>),
-    Variable(Token),
+    Variable(Token, Option<usize>),
+    Call(Box<Expr>, Token, Vec<Expr>),
+    Get(Box<Expr>, Token),
+    // The token is the closing bracket, for reporting errors.
+    Index(Box<Expr>, Token, Box<Expr>),
+    Set(Box<Expr>, Token, Box<Expr>),
+    This(Token, Option<usize>),
+    Super(Token, Token, Option<usize>),
 }
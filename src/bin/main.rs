@@ -1,6 +1,20 @@
 use rlox::Lox;
+use std::panic;
+use std::thread;
+
+// Enough native stack for scripts to recurse to the interpreter's default
+// call depth limit, even in a debug build.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
 
 fn main() {
-    let mut lox: Lox = Lox::new();
-    lox.init();
+    let interpreter = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| {
+            let mut lox: Lox = Lox::new();
+            lox.init();
+        })
+        .expect("failed to start the interpreter thread");
+    if let Err(panic) = interpreter.join() {
+        panic::resume_unwind(panic);
+    }
 }
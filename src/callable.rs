@@ -0,0 +1,289 @@
+use crate::ast::Stmt;
+use crate::class::LoxInstance;
+use crate::environment::Environment;
+use crate::interpreter::{ExecResult, Interpreter, RuntimeError};
+use crate::token::{Key, Literal, Token};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+use std::time::Instant;
+
+// Anything that can appear to the left of a call's parentheses. The
+// interpreter checks the argument count against `arity` before calling, so
+// implementations can assume they get exactly that many arguments, or up to
+// `max_arity` if that's larger. `paren` is the call's closing parenthesis, for
+// reporting errors raised by the call. `name` is how errors refer to it.
+pub trait Callable: fmt::Display {
+    fn name(&self) -> &str;
+    fn arity(&self) -> usize;
+    fn max_arity(&self) -> usize {
+        self.arity()
+    }
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Literal>,
+    ) -> Result<Literal, RuntimeError>;
+    fn as_memoized(&self) -> Option<&MemoizedFunction> {
+        None
+    }
+}
+
+pub struct LoxFunction {
+    name: Token,
+    params: Vec<Token>,
+    body: Rc<Vec<Stmt>>,
+    // The environment the declaration was executed in, kept alive so the body
+    // can keep using the variables around it after that scope has exited.
+    closure: Rc<RefCell<Environment>>,
+}
+
+impl LoxFunction {
+    pub fn new(
+        name: Token,
+        params: Vec<Token>,
+        body: Vec<Stmt>,
+        closure: Rc<RefCell<Environment>>,
+    ) -> Self {
+        LoxFunction {
+            name,
+            params,
+            body: Rc::new(body),
+            closure,
+        }
+    }
+
+    // Returns a copy of this method whose body sees `this` as `instance`.
+    pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> LoxFunction {
+        let mut environment = Environment::from(Rc::clone(&self.closure));
+        environment.define(&"this".to_owned(), Literal::Instance(instance));
+        LoxFunction {
+            name: self.name.clone(),
+            params: self.params.clone(),
+            body: Rc::clone(&self.body),
+            closure: Rc::new(RefCell::new(environment)),
+        }
+    }
+}
+
+impl Callable for LoxFunction {
+    fn name(&self) -> &str {
+        &self.name.lexeme
+    }
+
+    fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    // Each call gets a fresh environment for its parameters, enclosed by the
+    // function's closure. A tail call back to this function comes back as
+    // `TailCall` and runs as another trip round the loop, with a fresh
+    // environment for the new arguments.
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        _paren: &Token,
+        mut arguments: Vec<Literal>,
+    ) -> Result<Literal, RuntimeError> {
+        let this_function = self as *const LoxFunction as *const ();
+        let outer = interpreter.current_function.replace(this_function);
+        let result = loop {
+            let mut environment = Environment::from(Rc::clone(&self.closure));
+            for (param, argument) in self.params.iter().zip(arguments) {
+                environment.define(&param.lexeme, argument);
+            }
+            match interpreter.execute_block(self.body.as_ref().clone(), environment) {
+                Ok(ExecResult::TailCall(next)) => arguments = next,
+                Ok(ExecResult::Return(value)) => break Ok(value),
+                // The parser keeps `break` and `continue` inside loops, so only
+                // falling off the end of the body gets here.
+                Ok(_) => break Ok(Literal::Nil),
+                Err(error) => break Err(error),
+            }
+        };
+        interpreter.current_function = outer;
+        result
+    }
+}
+
+impl fmt::Display for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.name.lexeme)
+    }
+}
+
+// The signature of a native function's Rust implementation. An `Err` message
+// is raised as a runtime error at the call site.
+pub type NativeFn = dyn Fn(&[Literal]) -> Result<Literal, String>;
+
+// A function implemented in Rust and exposed to scripts as a global.
+pub struct NativeFunction {
+    name: String,
+    arity: usize,
+    max_arity: usize,
+    function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(name: &str, arity: usize, max_arity: usize, function: Box<NativeFn>) -> Self {
+        NativeFunction {
+            name: name.to_owned(),
+            arity,
+            max_arity,
+            function,
+        }
+    }
+}
+
+impl Callable for NativeFunction {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn max_arity(&self) -> usize {
+        self.max_arity
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Literal>,
+    ) -> Result<Literal, RuntimeError> {
+        (self.function)(&arguments).map_err(|message| RuntimeError::new(paren.clone(), message))
+    }
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+// What `memoize(f)` returns: calls `f` once per distinct list of arguments and
+// replays the result after that. Arguments are compared as `Key`s, so lists
+// and instances match only themselves, and a NaN argument is an error.
+// Errors aren't cached.
+pub struct MemoizedFunction {
+    function: Rc<dyn Callable>,
+    cache: RefCell<HashMap<Vec<Key>, Literal>>,
+}
+
+impl MemoizedFunction {
+    pub fn new(function: Rc<dyn Callable>) -> Self {
+        MemoizedFunction {
+            function,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn clear_cache(&self) {
+        self.cache.borrow_mut().clear();
+    }
+}
+
+impl Callable for MemoizedFunction {
+    fn name(&self) -> &str {
+        self.function.name()
+    }
+
+    fn arity(&self) -> usize {
+        self.function.arity()
+    }
+
+    fn max_arity(&self) -> usize {
+        self.function.max_arity()
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Literal>,
+    ) -> Result<Literal, RuntimeError> {
+        let key = match arguments.iter().cloned().map(Key::new).collect() {
+            Some(key) => key,
+            None => {
+                return Err(RuntimeError::new(
+                    paren.clone(),
+                    "Can't memoize a call with a NaN argument.".to_owned(),
+                ))
+            }
+        };
+        if let Some(result) = self.cache.borrow().get(&key) {
+            return Ok(result.clone());
+        }
+        // The cache isn't borrowed during the call, which may recurse back
+        // into this function.
+        let result = self.function.call(interpreter, paren, arguments)?;
+        self.cache.borrow_mut().insert(key, result.clone());
+        Ok(result)
+    }
+
+    fn as_memoized(&self) -> Option<&MemoizedFunction> {
+        Some(self)
+    }
+}
+
+impl fmt::Display for MemoizedFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<memoized fn {}>", self.function.name())
+    }
+}
+
+// The `profile(f)` native: calls `f` with no arguments and returns its
+// result, writing how long the call took to the interpreter's diagnostics
+// (stderr by default). The time is written even if `f` fails.
+pub struct Profile;
+
+impl Callable for Profile {
+    fn name(&self) -> &str {
+        "profile"
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        paren: &Token,
+        arguments: Vec<Literal>,
+    ) -> Result<Literal, RuntimeError> {
+        let function = match &arguments[0] {
+            Literal::Callable(function) => Rc::clone(function),
+            _ => {
+                return Err(RuntimeError::new(
+                    paren.clone(),
+                    "Argument to 'profile' must be a function.".to_owned(),
+                ))
+            }
+        };
+        Interpreter::check_arity(function.as_ref(), paren, 0)?;
+        let start = Instant::now();
+        let result = function.call(interpreter, paren, Vec::new());
+        let elapsed = start.elapsed();
+        writeln!(
+            interpreter.diagnostics,
+            "[profile] {}: {:.3}ms",
+            function.name(),
+            elapsed.as_secs_f64() * 1000.0
+        )
+        .expect("failed to write diagnostics");
+        result
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn profile>")
+    }
+}
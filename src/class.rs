@@ -0,0 +1,164 @@
+use crate::callable::{Callable, LoxFunction};
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::token::{Literal, Token};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+pub struct LoxClass {
+    pub name: String,
+    superclass: Option<Rc<LoxClass>>,
+    methods: HashMap<String, Rc<LoxFunction>>,
+    // Results of `find_method`, including misses, so a hot call through a
+    // deep hierarchy walks it only once. A class's methods can't change after
+    // it's declared, so entries never go stale.
+    cache: RefCell<HashMap<String, Option<Rc<LoxFunction>>>>,
+}
+
+impl LoxClass {
+    pub fn new(
+        name: String,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<String, Rc<LoxFunction>>,
+    ) -> Self {
+        LoxClass {
+            name,
+            superclass,
+            methods,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    // Methods defined on the class override those inherited from its
+    // superclasses.
+    pub fn find_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        if let Some(method) = self.cache.borrow().get(name) {
+            return method.clone();
+        }
+        let method = self.lookup_method(name);
+        self.cache
+            .borrow_mut()
+            .insert(name.to_owned(), method.clone());
+        method
+    }
+
+    fn lookup_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        match self.methods.get(name) {
+            Some(method) => Some(Rc::clone(method)),
+            None => self
+                .superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name)),
+        }
+    }
+}
+
+// Calling a class constructs a new, empty instance of it.
+impl Callable for Rc<LoxClass> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn arity(&self) -> usize {
+        0
+    }
+
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        _paren: &Token,
+        _arguments: Vec<Literal>,
+    ) -> Result<Literal, RuntimeError> {
+        let instance = LoxInstance::new(Rc::clone(self));
+        Ok(Literal::Instance(Rc::new(RefCell::new(instance))))
+    }
+}
+
+impl fmt::Display for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub struct LoxInstance {
+    pub class: Rc<LoxClass>,
+    pub fields: HashMap<String, Literal>,
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> Self {
+        LoxInstance {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+}
+
+// Fields shadow methods of the same name. Methods come back bound to the
+// instance they were read from.
+pub fn get(instance: &Rc<RefCell<LoxInstance>>, name: &Token) -> Result<Literal, RuntimeError> {
+    if let Some(value) = instance.borrow().fields.get(&name.lexeme) {
+        return Ok(value.clone());
+    }
+    let method = instance.borrow().class.find_method(&name.lexeme);
+    match method {
+        Some(method) => Ok(Literal::Callable(Rc::new(method.bind(Rc::clone(instance))))),
+        None => Err(RuntimeError::new(
+            name.clone(),
+            format!("Undefined property '{}'.", name.lexeme),
+        )),
+    }
+}
+
+impl fmt::Display for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Lox;
+    use std::io;
+
+    fn run(source: &str) -> Interpreter {
+        let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+        let statements = Lox::compile(source, false).unwrap();
+        interpreter.interpret(statements).unwrap();
+        interpreter
+    }
+
+    fn class(interpreter: &Interpreter, name: &str) -> Rc<LoxClass> {
+        match interpreter.get_global(name) {
+            Some(Literal::Class(class)) => class,
+            value => panic!("expected a class, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn repeated_calls_use_the_cached_method() {
+        let interpreter = run("
+            class A { name() { return \"A\"; } }
+            class B < A {}
+            class C < B { name() { return \"C\" + super.name(); } }
+            var names = \"\";
+            for (var i = 0; i < 3; i = i + 1) names = names + C().name() + B().name();
+        ");
+        assert_eq!(
+            interpreter.get_global("names"),
+            Some(Literal::String("CAACAACAA".to_owned()))
+        );
+        let c = class(&interpreter, "C");
+        assert!(c.cache.borrow().contains_key("name"));
+    }
+
+    #[test]
+    fn misses_are_cached_too() {
+        let interpreter = run("class A {}");
+        let a = class(&interpreter, "A");
+        assert!(a.find_method("missing").is_none());
+        assert!(matches!(a.cache.borrow().get("missing"), Some(None)));
+    }
+}
@@ -4,10 +4,12 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
+// Each binding lives in its own shared cell, so copies of an environment (and
+// anything that captured one) observe each other's assignments.
 #[derive(PartialEq, Clone, Debug, Default)]
 pub struct Environment {
     pub enclosing: Option<Rc<RefCell<Environment>>>,
-    pub values: HashMap<String, Literal>,
+    pub values: HashMap<String, Rc<RefCell<Literal>>>,
 }
 
 impl Environment {
@@ -18,45 +20,176 @@ impl Environment {
         }
     }
 
-    pub fn from(enclosing: Environment) -> Environment {
+    pub fn from(enclosing: Rc<RefCell<Environment>>) -> Environment {
         Environment {
-            enclosing: Some(Rc::new(RefCell::new(enclosing))),
+            enclosing: Some(enclosing),
             values: HashMap::new(),
         }
     }
 
     pub fn define(&mut self, name: &String, value: Literal) {
-        self.values.insert(name.to_owned(), value);
+        self.values
+            .insert(name.to_owned(), Rc::new(RefCell::new(value)));
     }
 
     pub fn get(&self, name: Token) -> Result<Literal, RuntimeError> {
-        if self.values.contains_key(&name.lexeme) {
-            Ok(self.values.get(&name.lexeme).unwrap().to_owned())
-        } else if self.enclosing.is_some() {
-            self.enclosing.as_ref().unwrap().borrow().get(name)
-        } else {
-            Err(RuntimeError::new(
-                name.clone(),
-                format!("Undefined variable {}.", &name.lexeme),
-            ))
+        match self.cell(&name.lexeme) {
+            Some(cell) => Ok(cell.borrow().clone()),
+            None => Err(self.undefined(name)),
         }
     }
 
     pub fn assign(&mut self, name: Token, value: Literal) -> Result<(), RuntimeError> {
-        if self.values.contains_key(&name.lexeme) {
-            self.values.insert(name.lexeme, value);
-            Ok(())
-        } else if self.enclosing.is_some() {
-            self.enclosing
-                .as_mut()
-                .unwrap()
-                .borrow_mut()
-                .assign(name, value)
-        } else {
-            Err(RuntimeError::new(
-                name.clone(),
-                format!("Undefined variable {}.", &name.lexeme),
-            ))
+        match self.cell(&name.lexeme) {
+            Some(cell) => {
+                *cell.borrow_mut() = value;
+                Ok(())
+            }
+            None => Err(self.undefined(name)),
         }
     }
+
+    // Reads `name` from the environment `distance` links up the chain, where
+    // the resolver found its declaration.
+    pub fn get_at(&self, distance: usize, name: Token) -> Result<Literal, RuntimeError> {
+        match self.cell_at(distance, &name.lexeme) {
+            Some(cell) => Ok(cell.borrow().clone()),
+            None => Err(self.undefined(name)),
+        }
+    }
+
+    pub fn assign_at(
+        &mut self,
+        distance: usize,
+        name: Token,
+        value: Literal,
+    ) -> Result<(), RuntimeError> {
+        match self.cell_at(distance, &name.lexeme) {
+            Some(cell) => {
+                *cell.borrow_mut() = value;
+                Ok(())
+            }
+            None => Err(self.undefined(name)),
+        }
+    }
+
+    fn cell_at(&self, distance: usize, name: &str) -> Option<Rc<RefCell<Literal>>> {
+        if distance == 0 {
+            return self.values.get(name).cloned();
+        }
+        self.enclosing
+            .as_ref()
+            .and_then(|enclosing| enclosing.borrow().cell_at(distance - 1, name))
+    }
+
+    fn cell(&self, name: &str) -> Option<Rc<RefCell<Literal>>> {
+        match self.values.get(name) {
+            Some(cell) => Some(Rc::clone(cell)),
+            None => self
+                .enclosing
+                .as_ref()
+                .and_then(|enclosing| enclosing.borrow().cell(name)),
+        }
+    }
+
+    fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.values.keys().cloned().collect();
+        if let Some(enclosing) = &self.enclosing {
+            names.extend(enclosing.borrow().names());
+        }
+        names
+    }
+
+    pub fn undefined(&self, name: Token) -> RuntimeError {
+        let mut message = format!("Undefined variable '{}'.", name.lexeme);
+        if let Some(suggestion) = self.suggest(&name.lexeme) {
+            message.push_str(&format!(" Did you mean '{}'?", suggestion));
+        }
+        RuntimeError::new(name, message)
+    }
+
+    // Finds the in-scope name closest to `name`, as long as it is close enough
+    // to plausibly be a typo of it. Names shorter than three characters get
+    // no suggestion, since any other short name is as close as a typo.
+    fn suggest(&self, name: &str) -> Option<String> {
+        let length = name.chars().count();
+        if length < 3 {
+            return None;
+        }
+        let max_distance = (length / 3).max(1);
+        self.names()
+            .into_iter()
+            .map(|candidate| (levenshtein(name, &candidate), candidate))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min()
+            .map(|(_, candidate)| candidate)
+    }
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::TokenType;
+
+    fn name(lexeme: &str) -> Token {
+        Token::new(TokenType::Identifier, lexeme.to_owned(), None, 1, 1)
+    }
+
+    #[test]
+    fn copies_share_each_binding() {
+        let mut original = Environment::new();
+        original.define(&"count".to_owned(), Literal::Number(0.0));
+        let mut copy = original.clone();
+        copy.assign(name("count"), Literal::Number(1.0)).unwrap();
+        assert_eq!(original.get(name("count")).unwrap(), Literal::Number(1.0));
+    }
+
+    fn undefined_message(environment: &Environment, lexeme: &str) -> String {
+        environment.get(name(lexeme)).unwrap_err().message
+    }
+
+    #[test]
+    fn suggests_a_close_name() {
+        let mut environment = Environment::new();
+        environment.define(&"count".to_owned(), Literal::Nil);
+        environment.define(&"total".to_owned(), Literal::Nil);
+        assert_eq!(
+            undefined_message(&environment, "cont"),
+            "Undefined variable 'cont'. Did you mean 'count'?"
+        );
+        assert_eq!(
+            undefined_message(&environment, "width"),
+            "Undefined variable 'width'."
+        );
+    }
+
+    #[test]
+    fn short_names_get_no_suggestion() {
+        let mut environment = Environment::new();
+        for name in &["x", "y", "ab"] {
+            environment.define(&name.to_string(), Literal::Nil);
+        }
+        assert_eq!(
+            undefined_message(&environment, "z"),
+            "Undefined variable 'z'."
+        );
+        assert_eq!(
+            undefined_message(&environment, "ac"),
+            "Undefined variable 'ac'."
+        );
+    }
 }
@@ -1,12 +1,20 @@
 use crate::ast::{Expr, Stmt};
+use crate::callable::{Callable, LoxFunction, NativeFunction, Profile};
+use crate::class::{self, LoxClass};
 use crate::environment::Environment;
+use crate::ir::Instruction;
+use crate::natives;
 use crate::token::Literal;
 use crate::token::Token;
 use crate::token::TokenType;
-use crate::Lox;
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::error;
 use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub enum Value {
@@ -17,131 +25,520 @@ pub enum Value {
 }
 
 pub struct Interpreter {
-    environment: Environment,
+    globals: Rc<RefCell<Environment>>,
+    environment: Rc<RefCell<Environment>>,
+    strict_conditions: bool,
+    nil_arithmetic: bool,
+    string_coercion: bool,
+    echo: bool,
+    max_statements: Option<usize>,
+    statements_executed: usize,
+    max_call_depth: usize,
+    call_depth: usize,
+    output: Box<dyn Write>,
+    // Where `profile` reports timings, apart from what scripts print.
+    pub(crate) diagnostics: Box<dyn Write>,
+    // Shared with the `random`, `random_int` and `seed` natives.
+    random: Rc<RefCell<natives::Random>>,
+    // Where the `input` native reads lines from.
+    input: Rc<RefCell<Box<dyn BufRead>>>,
+    // The Lox function whose body is running, by address, so a `return` can
+    // recognise a call back to it as a tail call.
+    pub(crate) current_function: Option<*const ()>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Interpreter {
-        Interpreter {
-            environment: Environment::new(),
+        Interpreter::with_output(Box::new(io::stdout()))
+    }
+
+    // Sends everything scripts print (and the REPL echoes) to `output` rather
+    // than stdout, e.g. so a host or test can capture it. Errors are still
+    // reported on the process's own streams.
+    pub fn with_output(output: Box<dyn Write>) -> Interpreter {
+        // Unbuffered, so `input()` never reads past the end of its line and
+        // steals what the REPL would read next.
+        let stdin = BufReader::with_capacity(1, io::stdin());
+        Interpreter::with_io(Box::new(stdin), output)
+    }
+
+    // Like `with_output`, but the `input` native also reads from `input`
+    // rather than stdin.
+    pub fn with_io(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Interpreter {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let mut interpreter = Interpreter {
+            environment: Rc::clone(&globals),
+            globals,
+            strict_conditions: false,
+            nil_arithmetic: false,
+            string_coercion: false,
+            echo: false,
+            max_statements: None,
+            statements_executed: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            call_depth: 0,
+            output,
+            diagnostics: Box::new(io::stderr()),
+            random: Rc::new(RefCell::new(natives::Random::new())),
+            input: Rc::new(RefCell::new(input)),
+            current_function: None,
+        };
+        interpreter.register_native("clock", 0, natives::clock);
+        interpreter.register_native("len", 1, natives::len);
+        interpreter.register_native("substring", 3, natives::substring);
+        interpreter.register_native("upper", 1, natives::upper);
+        interpreter.register_native("lower", 1, natives::lower);
+        interpreter.register_native("trim", 1, natives::trim);
+        interpreter.register_native("split", 2, natives::split);
+        interpreter.register_native_range("range", 1, 3, natives::range);
+        interpreter.register_native("contains", 2, natives::contains);
+        interpreter.register_native_range("min", 1, usize::MAX, natives::min);
+        interpreter.register_native_range("max", 1, usize::MAX, natives::max);
+        interpreter.register_native_range("sum", 1, usize::MAX, natives::sum);
+        interpreter.register_native_range("set", 0, 1, natives::set);
+        interpreter.register_native("add", 2, natives::add);
+        interpreter.register_native("has", 2, natives::has);
+        interpreter.register_native("remove", 2, natives::remove);
+        interpreter.register_native("union", 2, natives::union);
+        interpreter.register_native("intersection", 2, natives::intersection);
+        interpreter.register_native("memoize", 1, natives::memoize);
+        interpreter.register_native("clearCache", 1, natives::clear_cache);
+        interpreter.register_native("bytes", 1, natives::bytes);
+        interpreter.register_native("fromBytes", 1, natives::from_bytes);
+        interpreter.register_native("base64Encode", 1, natives::base64_encode);
+        interpreter.register_native("base64Decode", 1, natives::base64_decode);
+        interpreter.set_global("profile", Literal::Callable(Rc::new(Profile)));
+        interpreter.register_native("sqrt", 1, natives::sqrt);
+        interpreter.register_native("abs", 1, natives::abs);
+        interpreter.register_native("floor", 1, natives::floor);
+        interpreter.register_native("ceil", 1, natives::ceil);
+        interpreter.register_native("pow", 2, natives::pow);
+        interpreter.register_native("num", 1, natives::num);
+        interpreter.register_native("str", 1, natives::str);
+        interpreter.register_native("typeof", 1, natives::type_of);
+        interpreter.register_native_range("assert", 1, 2, natives::assert);
+        interpreter.register_random_natives();
+        let input = Rc::clone(&interpreter.input);
+        interpreter.register_native("input", 0, move |_| {
+            natives::input(input.borrow_mut().as_mut())
+        });
+        interpreter
+    }
+
+    // Exposes a Rust function to scripts as a global, replacing any global of
+    // the same name. Calls are checked against `arity` before `function` runs,
+    // so it always receives exactly that many arguments. An `Err` it returns
+    // becomes a runtime error at the call site.
+    pub fn register_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&[Literal]) -> Result<Literal, String> + 'static,
+    {
+        self.register_native_range(name, arity, arity, function);
+    }
+
+    // Like `register_native`, for a function taking anywhere from `min_arity`
+    // to `max_arity` arguments. A `max_arity` of `usize::MAX` means there's no
+    // upper limit.
+    pub fn register_native_range<F>(
+        &mut self,
+        name: &str,
+        min_arity: usize,
+        max_arity: usize,
+        function: F,
+    ) where
+        F: Fn(&[Literal]) -> Result<Literal, String> + 'static,
+    {
+        let native = NativeFunction::new(name, min_arity, max_arity, Box::new(function));
+        self.set_global(name, Literal::Callable(Rc::new(native)));
+    }
+
+    fn register_random_natives(&mut self) {
+        let random = Rc::clone(&self.random);
+        self.register_native("random", 0, move |_| {
+            Ok(Literal::Number(random.borrow_mut().next_f64()))
+        });
+        let random = Rc::clone(&self.random);
+        self.register_native("random_int", 2, move |arguments| {
+            natives::random_int(&mut random.borrow_mut(), arguments)
+        });
+        let random = Rc::clone(&self.random);
+        self.register_native("seed", 1, move |arguments| match arguments[0] {
+            Literal::Number(n) => {
+                random.borrow_mut().seed(n);
+                Ok(Literal::Nil)
+            }
+            _ => Err("Argument to 'seed' must be a number.".to_owned()),
+        });
+    }
+
+    // Every check that catches likely bugs at the cost of rejecting programs
+    // plain Lox accepts: boolean-only conditions, with nil arithmetic and
+    // string coercion off. Setters called afterwards still override it.
+    pub fn strict(mut self) -> Self {
+        self.set_strict(true);
+        self
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.set_strict_conditions(strict);
+        if strict {
+            self.set_nil_arithmetic(false);
+            self.set_string_coercion(false);
         }
     }
 
-    pub fn interpret(&mut self, statements: Vec<Box<Stmt>>) {
+    // When enabled, `if`/`while`/`for` conditions must evaluate to a boolean
+    // instead of being coerced with the usual truthiness rules.
+    pub fn set_strict_conditions(&mut self, strict_conditions: bool) {
+        self.strict_conditions = strict_conditions;
+    }
+
+    // When enabled, arithmetic with a `nil` operand yields `nil` (SQL-style)
+    // rather than raising a runtime error.
+    pub fn set_nil_arithmetic(&mut self, nil_arithmetic: bool) {
+        self.nil_arithmetic = nil_arithmetic;
+    }
+
+    // When enabled, `+` with a string on either side converts the other
+    // operand as `print` would and concatenates, so `"n: " + 5` is `"n: 5"`.
+    pub fn set_string_coercion(&mut self, string_coercion: bool) {
+        self.string_coercion = string_coercion;
+    }
+
+    // Sends `profile` timings to `diagnostics` rather than stderr.
+    pub fn set_diagnostics(&mut self, diagnostics: Box<dyn Write>) {
+        self.diagnostics = diagnostics;
+    }
+
+    // When enabled, the value of every expression statement is printed, as
+    // the REPL does.
+    pub fn set_echo(&mut self, echo: bool) {
+        self.echo = echo;
+    }
+
+    // Caps the number of statements and calls a single `interpret` call may
+    // execute, so untrusted or fuzzed input can't hang the interpreter. Only
+    // loops and calls can run code more than once, so the cap is checked on
+    // each loop iteration and each call, including tail calls.
+    pub fn set_max_statements(&mut self, max_statements: Option<usize>) {
+        self.max_statements = max_statements;
+    }
+
+    // Caps how deeply calls may nest before a script fails with "Stack
+    // overflow." instead of overflowing the native stack. Each level takes
+    // several KB of native stack, tens of KB in debug builds, so the default
+    // assumes a thread with a large stack, like the one `rlox` runs scripts
+    // on. Hosts on smaller stacks should lower it.
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    // Lets a host define or overwrite a global before running a script, and
+    // read globals back afterwards.
+    pub fn set_global(&mut self, name: &str, value: Literal) {
+        self.globals.borrow_mut().define(&name.to_owned(), value);
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Literal> {
+        let globals = self.globals.borrow();
+        globals.values.get(name).map(|cell| cell.borrow().clone())
+    }
+
+    pub fn globals(&self) -> Rc<RefCell<Environment>> {
+        Rc::clone(&self.globals)
+    }
+
+    // Stops at the first runtime error and returns it. `statements` must have
+    // been through the resolver, as `Lox::compile` does.
+    pub fn interpret(&mut self, statements: Vec<Box<Stmt>>) -> Result<(), RuntimeError> {
+        self.statements_executed = 0;
         for statement in statements {
-            self.interpret_statement(statement).unwrap();
+            self.interpret_statement(*statement)?;
         }
+        Ok(())
     }
 
-    fn interpret_statement(
-        &mut self,
-        statement: Box<Stmt>,
-    ) -> Result<Option<Literal>, RuntimeError> {
-        match *statement {
+    // Executes lowered IR on an explicit value stack. This shares operator
+    // semantics and the environment with the tree-walker, so for the subset
+    // the IR covers both produce the same output. The modes apply too, but the
+    // IR has no calls, so only loops can run without end; the budget counts
+    // each trip round a loop rather than each statement.
+    pub fn run_vm(&mut self, code: &[Instruction]) -> Result<(), RuntimeError> {
+        self.statements_executed = 0;
+        let environment = Rc::clone(&self.environment);
+        let result = self.execute(code);
+        if result.is_err() {
+            self.environment = environment;
+        }
+        result
+    }
+
+    fn execute(&mut self, code: &[Instruction]) -> Result<(), RuntimeError> {
+        let mut stack: Vec<Literal> = Vec::new();
+        let mut ip = 0;
+        while ip < code.len() {
+            match &code[ip] {
+                Instruction::Constant(literal) => stack.push(literal.clone()),
+                Instruction::Unary(op) => {
+                    let right = stack.pop().expect("VM stack underflow");
+                    stack.push(self.unary(op.clone(), right)?);
+                }
+                Instruction::Binary(op) => {
+                    let rhs = stack.pop().expect("VM stack underflow");
+                    let lhs = stack.pop().expect("VM stack underflow");
+                    stack.push(self.binary(lhs, op.clone(), rhs)?);
+                }
+                Instruction::DefineVar(name) => {
+                    let value = stack.pop().expect("VM stack underflow");
+                    self.environment.borrow_mut().define(name, value);
+                }
+                Instruction::GetVar(name) => {
+                    stack.push(self.environment.borrow().get(name.clone())?)
+                }
+                Instruction::SetVar(name) => {
+                    let value = stack.last().expect("VM stack underflow").clone();
+                    self.environment.borrow_mut().assign(name.clone(), value)?;
+                }
+                Instruction::Pop => {
+                    stack.pop();
+                }
+                Instruction::PopExpr => {
+                    let value = stack.pop().expect("VM stack underflow");
+                    if self.echo {
+                        self.echo(value);
+                    }
+                }
+                Instruction::Print => self.print(stack.pop().expect("VM stack underflow")),
+                Instruction::PushScope => {
+                    let enclosing = Rc::clone(&self.environment);
+                    self.environment = Rc::new(RefCell::new(Environment::from(enclosing)));
+                }
+                Instruction::PopScope => {
+                    let enclosing = self.environment.borrow().enclosing.clone();
+                    if let Some(enclosing) = enclosing {
+                        self.environment = enclosing;
+                    }
+                }
+                Instruction::Jump(target) => {
+                    ip = *target;
+                    continue;
+                }
+                Instruction::JumpIfFalse(target) => {
+                    let condition = stack.last().expect("VM stack underflow").clone();
+                    if !Interpreter::is_truthy(condition) {
+                        ip = *target;
+                        continue;
+                    }
+                }
+                Instruction::Condition(keyword) => {
+                    let condition = stack.last().expect("VM stack underflow");
+                    if self.strict_conditions && !matches!(condition, Literal::Bool(_)) {
+                        return Err(RuntimeError::new(
+                            keyword.clone(),
+                            "Condition must be a boolean.".to_owned(),
+                        ));
+                    }
+                }
+                Instruction::Loop(keyword, target) => {
+                    self.statements_executed += 1;
+                    self.check_budget(keyword)?;
+                    ip = *target;
+                    continue;
+                }
+            }
+            ip += 1;
+        }
+        Ok(())
+    }
+
+    fn interpret_statement(&mut self, statement: Stmt) -> Result<ExecResult, RuntimeError> {
+        self.statements_executed += 1;
+        match statement {
             Stmt::Expr(expr) => {
-                let expression = self.evaluate(expr);
-                match expression {
-                    Ok(l) => {
-                        // match l.clone() {
-                        //     Literal::Bool(b) => {
-                        //         println!("{}", b);
-                        //     }
-                        //     Literal::Number(n) => {
-                        //         println!("{}", n);
-                        //     }
-                        //     Literal::String(s) => {
-                        //         println!("\"{}\"", s);
-                        //     }
-                        //     Literal::Nil => {
-                        //         println!("nil");
-                        //     }
-                        // };
-                        // None
-                        Ok(Some(l))
+                let value = self.evaluate(*expr)?;
+                if self.echo {
+                    self.echo(value);
+                }
+                Ok(ExecResult::Normal)
+            }
+            Stmt::While(keyword, condition, body, increment) => {
+                while self.evaluate_condition(&keyword, *condition.clone())? {
+                    self.check_budget(&keyword)?;
+                    match self.interpret_statement(*body.to_owned())? {
+                        ExecResult::Break => break,
+                        result @ ExecResult::Return(_) | result @ ExecResult::TailCall(_) => {
+                            return Ok(result)
+                        }
+                        ExecResult::Normal | ExecResult::Continue => {}
                     }
-                    Err(e) => {
-                        Lox::runtime_error(e);
-                        Ok(None)
+                    if let Some(increment) = &increment {
+                        self.evaluate(*increment.clone())?;
                     }
                 }
+                Ok(ExecResult::Normal)
             }
-            Stmt::While(condition, body) => {
-                while Interpreter::is_truthy(self.evaluate(condition.clone())?) {
-                    self.interpret_statement(body.to_owned())?;
+
+            // Each iteration gets a fresh environment binding `name`, so
+            // closures created in the body capture that iteration's element.
+            // Elements are copied out first, so changing the list inside the
+            // loop doesn't change what's iterated.
+            Stmt::ForIn(keyword, name, iterable, body) => {
+                let elements = match self.evaluate(*iterable)? {
+                    Literal::List(list) => list.borrow().clone(),
+                    _ => {
+                        return Err(RuntimeError::new(
+                            keyword,
+                            "Can only iterate over a list.".to_owned(),
+                        ))
+                    }
+                };
+                for element in elements {
+                    self.check_budget(&keyword)?;
+                    let mut environment = Environment::from(Rc::clone(&self.environment));
+                    environment.define(&name.lexeme, element);
+                    match self.execute_block(vec![*body.clone()], environment)? {
+                        ExecResult::Break => break,
+                        result @ ExecResult::Return(_) | result @ ExecResult::TailCall(_) => {
+                            return Ok(result)
+                        }
+                        ExecResult::Normal | ExecResult::Continue => {}
+                    }
                 }
-                Ok(None)
+                Ok(ExecResult::Normal)
             }
 
-            Stmt::If(condition, then_branch, else_branch) => {
-                if Interpreter::is_truthy(self.evaluate(condition).unwrap()) {
-                    self.interpret_statement(then_branch)
-                } else if else_branch.is_some() {
-                    self.interpret_statement(else_branch.unwrap())
+            Stmt::If(keyword, condition, then_branch, else_branch) => {
+                if self.evaluate_condition(&keyword, *condition)? {
+                    self.interpret_statement(*then_branch)
+                } else if let Some(else_branch) = else_branch {
+                    self.interpret_statement(*else_branch)
                 } else {
-                    Ok(None)
+                    Ok(ExecResult::Normal)
                 }
             }
             Stmt::Block(s) => {
-                self.environment = Environment::from(self.environment.clone());
-                for statement in s.iter() {
-                    self.interpret_statement(Box::new(statement.clone()))
-                        .unwrap();
-                }
-
-                if let Some(enclosing) = self.environment.enclosing.clone() {
-                    self.environment = enclosing.clone().take();
-                }
-                Ok(None)
+                let environment = Environment::from(Rc::clone(&self.environment));
+                self.execute_block(s, environment)
             }
             Stmt::Print(expr) => {
-                let value = self.evaluate(expr);
-                match value {
-                    Ok(l) => {
-                        match l {
-                            Literal::Bool(b) => {
-                                println!("{}", b);
-                            }
-                            Literal::Number(n) => {
-                                println!("{}", n);
-                            }
-                            Literal::String(s) => {
-                                println!("\"{}\"", s);
-                            }
-                            Literal::Nil => {
-                                println!("nil");
+                let value = self.evaluate(*expr)?;
+                self.print(value);
+                Ok(ExecResult::Normal)
+            }
+            Stmt::Var(name, initializer) => {
+                let mut value: Literal = Literal::Nil;
+                if let Some(e) = *initializer {
+                    value = self.evaluate(e)?;
+                }
+                self.environment.borrow_mut().define(&name.lexeme, value);
+                Ok(ExecResult::Normal)
+            }
+            Stmt::Function(name, params, body) => {
+                let closure = Rc::clone(&self.environment);
+                let function = LoxFunction::new(name.clone(), params, body, closure);
+                self.environment
+                    .borrow_mut()
+                    .define(&name.lexeme, Literal::Callable(Rc::new(function)));
+                Ok(ExecResult::Normal)
+            }
+            Stmt::Class(name, superclass, declarations) => {
+                let superclass = match superclass {
+                    Some(Expr::Variable(superclass_name, depth)) => {
+                        match self.look_up_variable(superclass_name.clone(), depth)? {
+                            Literal::Class(superclass) => Some(superclass),
+                            _ => {
+                                return Err(RuntimeError::new(
+                                    superclass_name,
+                                    "Superclass must be a class.".to_owned(),
+                                ))
                             }
-                        };
-                        Ok(None)
+                        }
                     }
-                    Err(e) => {
-                        Lox::runtime_error(e);
-                        Ok(None)
+                    _ => None,
+                };
+                // Methods of a subclass close over an extra scope binding
+                // `super`, so `super.method()` can find where to start looking.
+                let mut closure = Rc::clone(&self.environment);
+                if let Some(superclass) = &superclass {
+                    let mut environment = Environment::from(closure);
+                    environment.define(&"super".to_owned(), Literal::Class(Rc::clone(superclass)));
+                    closure = Rc::new(RefCell::new(environment));
+                }
+                let mut methods = HashMap::new();
+                for declaration in declarations {
+                    if let Stmt::Function(method, params, body) = declaration {
+                        let closure = Rc::clone(&closure);
+                        let function = LoxFunction::new(method.clone(), params, body, closure);
+                        methods.insert(method.lexeme, Rc::new(function));
                     }
                 }
+                let class = LoxClass::new(name.lexeme.clone(), superclass, methods);
+                self.environment
+                    .borrow_mut()
+                    .define(&name.lexeme, Literal::Class(Rc::new(class)));
+                Ok(ExecResult::Normal)
             }
-            Stmt::Var(name, initializer) => {
-                let mut value: Literal = Literal::Nil;
-                match *initializer {
-                    Some(e) => {
-                        let f = self.evaluate(Box::new(e));
-                        match f {
-                            Ok(e) => value = e,
-                            Err(e) => Lox::runtime_error(e),
-                        }
+            Stmt::Break(_) => Ok(ExecResult::Break),
+            Stmt::Continue(_) => Ok(ExecResult::Continue),
+            // A function returning a call to itself hands the arguments back
+            // to `LoxFunction::call` to run as another iteration, rather than
+            // recursing, so tail recursion runs in constant stack.
+            Stmt::Return(_, Some(Expr::Call(callee, paren, arguments)))
+                if self.current_function.is_some() =>
+            {
+                let callee = self.evaluate(*callee)?;
+                let arguments = self.evaluate_arguments(arguments)?;
+                match &callee {
+                    Literal::Callable(function)
+                        if Some(Rc::as_ptr(function) as *const ()) == self.current_function =>
+                    {
+                        self.count_call(&paren)?;
+                        Interpreter::check_arity(function.as_ref(), &paren, arguments.len())?;
+                        Ok(ExecResult::TailCall(arguments))
                     }
-                    None => {}
+                    _ => Ok(ExecResult::Return(self.call(callee, paren, arguments)?)),
                 }
-                self.environment.define(&name.lexeme, value);
-                Ok(None)
+            }
+            Stmt::Return(_, value) => {
+                let value = match value {
+                    Some(e) => self.evaluate(e)?,
+                    None => Literal::Nil,
+                };
+                Ok(ExecResult::Return(value))
+            }
+        }
+    }
+
+    // Runs `statements` in `environment`, restoring the current environment
+    // afterwards even if a statement fails.
+    pub(crate) fn execute_block(
+        &mut self,
+        statements: Vec<Stmt>,
+        environment: Environment,
+    ) -> Result<ExecResult, RuntimeError> {
+        let previous = mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+        let mut result = Ok(ExecResult::Normal);
+        for statement in statements {
+            result = self.interpret_statement(statement);
+            if !matches!(result, Ok(ExecResult::Normal)) {
+                break;
             }
         }
+        self.environment = previous;
+        result
     }
 
-    fn evaluate(&mut self, expr: Box<Expr>) -> Result<Literal, RuntimeError> {
-        match *expr {
+    pub(crate) fn evaluate(&mut self, expr: Expr) -> Result<Literal, RuntimeError> {
+        match expr {
             Expr::Literal(literal) => self.evaluate_literal(literal.to_owned()),
             Expr::Logical(lhs, op, rhs) => {
-                let left = self.evaluate(lhs)?;
+                let left = self.evaluate(*lhs)?;
                 if op.token_type == TokenType::Or {
                     if Interpreter::is_truthy(left.clone()) {
                         return Ok(left);
@@ -151,17 +548,231 @@ impl Interpreter {
                         return Ok(left);
                     }
                 }
-                self.evaluate(rhs)
+                self.evaluate(*rhs)
             }
-            Expr::Unary(op, e) => self.evaluate_unary(op.to_owned(), e),
-            Expr::Binary(lhs, op, rhs) => self.evaluate_binary(lhs, op.to_owned(), rhs),
-            Expr::Grouping(e) => self.evaluate(e),
-            Expr::Variable(e) => self.environment.get(e),
-            Expr::Assignment(t, e) => {
-                let value = self.evaluate(e)?;
-                self.environment.assign(t, value.clone()).unwrap();
+            Expr::Ternary(condition, then_branch, else_branch) => {
+                if Interpreter::is_truthy(self.evaluate(*condition)?) {
+                    self.evaluate(*then_branch)
+                } else {
+                    self.evaluate(*else_branch)
+                }
+            }
+            Expr::Unary(op, e) => self.evaluate_unary(op.to_owned(), *e),
+            Expr::Binary(lhs, op, rhs) => self.evaluate_binary(*lhs, op.to_owned(), *rhs),
+            Expr::Grouping(e) => self.evaluate(*e),
+            Expr::Variable(name, depth) => self.look_up_variable(name, depth),
+            Expr::This(keyword, depth) => self.look_up_variable(keyword, depth),
+            Expr::Super(keyword, method, depth) => self.evaluate_super(keyword, method, depth),
+            Expr::Assignment(t, e, depth) => {
+                let value = self.evaluate(*e)?;
+                match depth {
+                    Some(distance) => {
+                        self.environment
+                            .borrow_mut()
+                            .assign_at(distance, t, value.clone())?
+                    }
+                    None => {
+                        let assigned = self.globals.borrow_mut().assign(t.clone(), value.clone());
+                        assigned.map_err(|_| self.undefined(t))?
+                    }
+                }
                 Ok(value)
             }
+            Expr::Call(callee, paren, arguments) => {
+                let callee = self.evaluate(*callee)?;
+                let arguments = self.evaluate_arguments(arguments)?;
+                self.call(callee, paren, arguments)
+            }
+            Expr::Get(object, name) => match self.evaluate(*object)? {
+                Literal::Instance(instance) => class::get(&instance, &name),
+                _ => Err(RuntimeError::new(
+                    name,
+                    "Only instances have properties.".to_owned(),
+                )),
+            },
+            Expr::Index(object, bracket, index) => {
+                let object = self.evaluate(*object)?;
+                let index = self.evaluate(*index)?;
+                match object {
+                    Literal::String(s) => {
+                        let chars: Vec<char> = s.chars().collect();
+                        natives::index(&index, chars.len())
+                            .map(|i| Literal::String(chars[i].to_string()))
+                            .map_err(|message| RuntimeError::new(bracket, message))
+                    }
+                    Literal::List(list) => {
+                        let list = list.borrow();
+                        natives::index(&index, list.len())
+                            .map(|i| list[i].clone())
+                            .map_err(|message| RuntimeError::new(bracket, message))
+                    }
+                    _ => Err(RuntimeError::new(
+                        bracket,
+                        "Only strings and lists can be indexed.".to_owned(),
+                    )),
+                }
+            }
+            Expr::Set(object, name, value) => {
+                let instance = match self.evaluate(*object)? {
+                    Literal::Instance(instance) => instance,
+                    _ => {
+                        return Err(RuntimeError::new(
+                            name,
+                            "Only instances have fields.".to_owned(),
+                        ))
+                    }
+                };
+                let value = self.evaluate(*value)?;
+                instance
+                    .borrow_mut()
+                    .fields
+                    .insert(name.lexeme, value.clone());
+                Ok(value)
+            }
+        }
+    }
+
+    fn call(
+        &mut self,
+        callee: Literal,
+        paren: Token,
+        arguments: Vec<Literal>,
+    ) -> Result<Literal, RuntimeError> {
+        let function: Rc<dyn Callable> = match callee {
+            Literal::Callable(function) => function,
+            Literal::Class(class) => Rc::new(class),
+            _ => {
+                return Err(RuntimeError::new(
+                    paren,
+                    "Can only call functions and classes.".to_owned(),
+                ))
+            }
+        };
+        self.count_call(&paren)?;
+        Interpreter::check_arity(function.as_ref(), &paren, arguments.len())?;
+        if self.call_depth >= self.max_call_depth {
+            return Err(RuntimeError::new(paren, "Stack overflow.".to_owned()));
+        }
+        self.call_depth += 1;
+        let result = function.call(self, &paren, arguments);
+        self.call_depth -= 1;
+        result
+    }
+
+    // A call counts against the budget like a statement does.
+    fn count_call(&mut self, paren: &Token) -> Result<(), RuntimeError> {
+        self.statements_executed += 1;
+        self.check_budget(paren)
+    }
+
+    fn evaluate_arguments(&mut self, arguments: Vec<Expr>) -> Result<Vec<Literal>, RuntimeError> {
+        arguments
+            .into_iter()
+            .map(|argument| self.evaluate(argument))
+            .collect()
+    }
+
+    pub(crate) fn check_arity(
+        function: &dyn Callable,
+        paren: &Token,
+        count: usize,
+    ) -> Result<(), RuntimeError> {
+        let (min, max) = (function.arity(), function.max_arity());
+        if count < min || count > max {
+            let expected = if min == max {
+                min.to_string()
+            } else if max == usize::MAX {
+                format!("at least {}", min)
+            } else {
+                format!("{} to {}", min, max)
+            };
+            return Err(RuntimeError::new(
+                paren.clone(),
+                format!(
+                    "Expected {} arguments to '{}' but got {}.",
+                    expected,
+                    function.name(),
+                    count
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    // Variables the resolver found in a local scope are read from exactly that
+    // scope; everything else is a global.
+    fn look_up_variable(&self, name: Token, depth: Option<usize>) -> Result<Literal, RuntimeError> {
+        match depth {
+            Some(distance) => self.environment.borrow().get_at(distance, name),
+            None => {
+                let value = self.globals.borrow().get(name.clone());
+                value.map_err(|_| self.undefined(name))
+            }
+        }
+    }
+
+    // A name the resolver left to the globals may still be a typo of a local,
+    // so the error suggests names from every scope the code can see.
+    fn undefined(&self, name: Token) -> RuntimeError {
+        self.environment.borrow().undefined(name)
+    }
+
+    // Looks `method` up starting at the superclass of the class whose method
+    // contains this expression, bound to the current `this`.
+    fn evaluate_super(
+        &mut self,
+        keyword: Token,
+        method: Token,
+        depth: Option<usize>,
+    ) -> Result<Literal, RuntimeError> {
+        let distance = depth.expect("'super' is always resolved to a local scope");
+        let superclass = match self.environment.borrow().get_at(distance, keyword)? {
+            Literal::Class(superclass) => superclass,
+            _ => unreachable!("'super' is always bound to a class"),
+        };
+        let this = Token::new(
+            TokenType::This,
+            "this".to_owned(),
+            None,
+            method.line,
+            method.column,
+        );
+        // `this` is bound in the scope just inside the one binding `super`.
+        let instance = match self.environment.borrow().get_at(distance - 1, this)? {
+            Literal::Instance(instance) => instance,
+            _ => unreachable!("'this' is always bound to an instance"),
+        };
+        match superclass.find_method(&method.lexeme) {
+            Some(function) => Ok(Literal::Callable(Rc::new(function.bind(instance)))),
+            None => Err(RuntimeError::new(
+                method.clone(),
+                format!("Undefined property '{}'.", method.lexeme),
+            )),
+        }
+    }
+
+    fn check_budget(&self, token: &Token) -> Result<(), RuntimeError> {
+        match self.max_statements {
+            Some(max) if self.statements_executed > max => Err(RuntimeError::new(
+                token.clone(),
+                "Execution budget exceeded.".to_owned(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn evaluate_condition(
+        &mut self,
+        keyword: &Token,
+        condition: Expr,
+    ) -> Result<bool, RuntimeError> {
+        match self.evaluate(condition)? {
+            Literal::Bool(b) => Ok(b),
+            _ if self.strict_conditions => Err(RuntimeError::new(
+                keyword.clone(),
+                "Condition must be a boolean.".to_owned(),
+            )),
+            value => Ok(Interpreter::is_truthy(value)),
         }
     }
 
@@ -169,13 +780,16 @@ impl Interpreter {
         Ok(expr)
     }
 
-    fn evaluate_unary(&mut self, op: Token, expr: Box<Expr>) -> Result<Literal, RuntimeError> {
+    fn evaluate_unary(&mut self, op: Token, expr: Expr) -> Result<Literal, RuntimeError> {
         let right = self.evaluate(expr)?;
+        self.unary(op, right)
+    }
 
+    fn unary(&self, op: Token, right: Literal) -> Result<Literal, RuntimeError> {
         match op.token_type {
-            TokenType::Bang => Ok(Literal::Bool(Interpreter::is_truthy(right))),
+            TokenType::Bang => Ok(Literal::Bool(!Interpreter::is_truthy(right))),
             TokenType::Minus => match right {
-                Literal::Number(f) => Ok(Literal::Number(f * -1 as f64)),
+                Literal::Number(f) => Ok(Literal::Number(-f)),
                 _ => Err(RuntimeError::new(
                     op,
                     "Invalid negation operand.".to_owned(),
@@ -187,49 +801,62 @@ impl Interpreter {
 
     fn evaluate_binary(
         &mut self,
-        left: Box<Expr>,
+        left: Expr,
         op: Token,
-        right: Box<Expr>,
+        right: Expr,
     ) -> Result<Literal, RuntimeError> {
         let lhs: Literal = self.evaluate(left)?;
         let rhs: Literal = self.evaluate(right)?;
+        self.binary(lhs, op, rhs)
+    }
 
+    fn binary(&self, lhs: Literal, op: Token, rhs: Literal) -> Result<Literal, RuntimeError> {
+        if self.nil_arithmetic && (lhs == Literal::Nil || rhs == Literal::Nil) {
+            if let TokenType::Minus
+            | TokenType::Plus
+            | TokenType::Slash
+            | TokenType::Star
+            | TokenType::Percent = op.token_type
+            {
+                return Ok(Literal::Nil);
+            }
+        }
+
+        // Strings compare lexicographically by character, as Rust's `str`
+        // ordering does.
         match op.token_type {
             TokenType::Greater => match (lhs, rhs) {
                 (Literal::Number(lhs), Literal::Number(rhs)) => Ok(Literal::Bool(lhs > rhs)),
+                (Literal::String(lhs), Literal::String(rhs)) => Ok(Literal::Bool(lhs > rhs)),
                 _ => Err(RuntimeError::new(
                     op,
-                    "Operands must be numbers.".to_owned(),
+                    "Operands must be two numbers or two strings.".to_owned(),
                 )),
             },
             TokenType::GreaterEqual => match (lhs, rhs) {
                 (Literal::Number(lhs), Literal::Number(rhs)) => Ok(Literal::Bool(lhs >= rhs)),
+                (Literal::String(lhs), Literal::String(rhs)) => Ok(Literal::Bool(lhs >= rhs)),
                 _ => Err(RuntimeError::new(
                     op,
-                    "Operands must be numbers.".to_owned(),
+                    "Operands must be two numbers or two strings.".to_owned(),
                 )),
             },
             TokenType::LessEqual => match (lhs, rhs) {
                 (Literal::Number(lhs), Literal::Number(rhs)) => Ok(Literal::Bool(lhs <= rhs)),
+                (Literal::String(lhs), Literal::String(rhs)) => Ok(Literal::Bool(lhs <= rhs)),
                 _ => Err(RuntimeError::new(
                     op,
-                    "Operands must be numbers.".to_owned(),
+                    "Operands must be two numbers or two strings.".to_owned(),
                 )),
             },
-            TokenType::EqualEqual => match (lhs, rhs) {
-                (Literal::Number(lhs), Literal::Number(rhs)) => Ok(Literal::Bool(lhs == rhs)),
-                (Literal::String(lhs), Literal::String(rhs)) => Ok(Literal::Bool(lhs == rhs)),
-                (Literal::String(_), Literal::Number(_)) => Ok(Literal::Bool(false)),
-                (Literal::Number(_), Literal::String(_)) => Ok(Literal::Bool(false)),
-                (Literal::Bool(lhs), Literal::Bool(rhs)) => Ok(Literal::Bool(lhs == rhs)),
-                (Literal::Nil, Literal::Nil) => Ok(Literal::Bool(true)),
-                _ => Ok(Literal::Bool(false)),
-            },
+            TokenType::EqualEqual => Ok(Literal::Bool(Interpreter::is_equal(&lhs, &rhs))),
+            TokenType::BangEqual => Ok(Literal::Bool(!Interpreter::is_equal(&lhs, &rhs))),
             TokenType::Less => match (lhs, rhs) {
                 (Literal::Number(lhs), Literal::Number(rhs)) => Ok(Literal::Bool(lhs < rhs)),
+                (Literal::String(lhs), Literal::String(rhs)) => Ok(Literal::Bool(lhs < rhs)),
                 _ => Err(RuntimeError::new(
                     op,
-                    "Operands must be numbers.".to_owned(),
+                    "Operands must be two numbers or two strings.".to_owned(),
                 )),
             },
             TokenType::Minus => match (lhs, rhs) {
@@ -240,16 +867,26 @@ impl Interpreter {
                 )),
             },
             TokenType::Plus => match (lhs, rhs) {
-                (Literal::Number(lhs), Literal::Number(rhs)) => {
-                    Ok(Literal::Number(lhs.clone() + rhs.clone()))
-                }
+                (Literal::Number(lhs), Literal::Number(rhs)) => Ok(Literal::Number(lhs + rhs)),
                 (Literal::String(lhs), Literal::String(rhs)) => Ok(Literal::String(lhs + &rhs)),
+                (lhs @ Literal::String(_), rhs) | (lhs, rhs @ Literal::String(_))
+                    if self.string_coercion =>
+                {
+                    Ok(Literal::String(
+                        Interpreter::stringify(&lhs) + &Interpreter::stringify(&rhs),
+                    ))
+                }
                 _ => Err(RuntimeError::new(
                     op,
                     "Operands must be either two numbers or two strings.".to_owned(),
                 )),
             },
+            // Any zero divisor is an error, `0 / 0` included, so division never
+            // produces an infinity or NaN.
             TokenType::Slash => match (lhs, rhs) {
+                (Literal::Number(_), Literal::Number(0.0)) => {
+                    Err(RuntimeError::new(op, "Division by zero.".to_owned()))
+                }
                 (Literal::Number(lhs), Literal::Number(rhs)) => Ok(Literal::Number(lhs / rhs)),
                 _ => Err(RuntimeError::new(
                     op,
@@ -258,6 +895,20 @@ impl Interpreter {
             },
             TokenType::Star => match (lhs, rhs) {
                 (Literal::Number(lhs), Literal::Number(rhs)) => Ok(Literal::Number(lhs * rhs)),
+                (Literal::String(s), Literal::Number(count))
+                | (Literal::Number(count), Literal::String(s)) => {
+                    Interpreter::repeat(&s, count, op)
+                }
+                _ => Err(RuntimeError::new(
+                    op,
+                    "Operands must be two numbers or a string and a number.".to_owned(),
+                )),
+            },
+            TokenType::Percent => match (lhs, rhs) {
+                (Literal::Number(_), Literal::Number(0.0)) => {
+                    Err(RuntimeError::new(op, "Modulo by zero.".to_owned()))
+                }
+                (Literal::Number(lhs), Literal::Number(rhs)) => Ok(Literal::Number(lhs % rhs)),
                 _ => Err(RuntimeError::new(
                     op,
                     "Operands must be numbers.".to_owned(),
@@ -270,7 +921,105 @@ impl Interpreter {
         }
     }
 
-    fn is_truthy(value: Literal) -> bool {
+    // `"ab" * 3` is `"ababab"`. The count has to be a whole number of
+    // repetitions, and the result no longer than `natives::MAX_LENGTH` bytes.
+    fn repeat(s: &str, count: f64, op: Token) -> Result<Literal, RuntimeError> {
+        if count < 0.0 || count.fract() != 0.0 {
+            return Err(RuntimeError::new(
+                op,
+                "Repetition count must be a non-negative whole number.".to_owned(),
+            ));
+        }
+        // Counts too big for a usize saturate, so they fail the check too.
+        let count = count as usize;
+        match s.len().checked_mul(count) {
+            Some(length) if length <= natives::MAX_LENGTH => Ok(Literal::String(s.repeat(count))),
+            _ => Err(RuntimeError::new(
+                op,
+                "Repeated string is too long.".to_owned(),
+            )),
+        }
+    }
+
+    // A failed write is treated like `println!` treats one on stdout.
+    fn print(&mut self, value: Literal) {
+        writeln!(self.output, "{}", Interpreter::stringify(&value))
+            .expect("failed to write output");
+    }
+
+    // The REPL echoes strings quoted so they can be told apart from other
+    // values, e.g. `"nil"` from `nil`.
+    fn echo(&mut self, value: Literal) {
+        match value {
+            Literal::String(s) => {
+                writeln!(self.output, "\"{}\"", s).expect("failed to write output")
+            }
+            value => self.print(value),
+        }
+    }
+
+    // How a value appears in `print` output.
+    pub fn stringify(value: &Literal) -> String {
+        match value {
+            Literal::String(s) => s.clone(),
+            _ => Interpreter::stringify_element(value, &mut Vec::new()),
+        }
+    }
+
+    // How `value` is written as part of a collection, where strings are quoted
+    // so `["1"]` and `[1]` look different. `visiting` holds the collections
+    // being written further out, so one that contains itself is written as
+    // `[...]` or `{...}` there instead of recursing forever.
+    fn stringify_element(value: &Literal, visiting: &mut Vec<*const ()>) -> String {
+        match value {
+            Literal::Bool(b) => b.to_string(),
+            // `f64`'s `Display` writes the shortest digits that round-trip and
+            // never uses an exponent or a trailing `.0`: `1e21` prints as
+            // `1000000000000000000000`, `0.5` as `0.5` and `-0.0` as `-0`.
+            Literal::Number(n) => n.to_string(),
+            Literal::String(s) => format!("\"{}\"", s),
+            Literal::Nil => "nil".to_owned(),
+            Literal::Callable(c) => c.to_string(),
+            Literal::Class(c) => c.to_string(),
+            Literal::Instance(i) => i.borrow().to_string(),
+            Literal::List(l) => {
+                let id = Rc::as_ptr(l) as *const ();
+                if visiting.contains(&id) {
+                    return "[...]".to_owned();
+                }
+                visiting.push(id);
+                let elements: Vec<String> = l
+                    .borrow()
+                    .iter()
+                    .map(|element| Interpreter::stringify_element(element, visiting))
+                    .collect();
+                visiting.pop();
+                format!("[{}]", elements.join(", "))
+            }
+            Literal::Set(s) => {
+                let id = Rc::as_ptr(s) as *const ();
+                if visiting.contains(&id) {
+                    return "{...}".to_owned();
+                }
+                visiting.push(id);
+                let members: Vec<String> = s
+                    .borrow()
+                    .iter()
+                    .map(|member| Interpreter::stringify_element(member, visiting))
+                    .collect();
+                visiting.pop();
+                format!("{{{}}}", members.join(", "))
+            }
+            Literal::Bytes(b) => format!("<bytes {}>", b.len()),
+        }
+    }
+
+    // Values of different types are never equal, and nil only equals nil.
+    fn is_equal(lhs: &Literal, rhs: &Literal) -> bool {
+        lhs == rhs
+    }
+
+    pub(crate) fn is_truthy(value: Literal) -> bool {
         match value {
             Literal::Nil => false,
             Literal::Bool(b) => b,
@@ -279,6 +1028,116 @@ impl Interpreter {
     }
 }
 
+// Configures an `Interpreter` in one expression, e.g.
+//
+//     let interpreter = InterpreterBuilder::new()
+//         .output(Box::new(buffer))
+//         .max_statements(10_000)
+//         .strict()
+//         .build();
+//
+// Anything not set keeps the default `Interpreter::new` uses.
+#[derive(Default)]
+pub struct InterpreterBuilder {
+    input: Option<Box<dyn BufRead>>,
+    output: Option<Box<dyn Write>>,
+    diagnostics: Option<Box<dyn Write>>,
+    strict: bool,
+    nil_arithmetic: bool,
+    string_coercion: bool,
+    echo: bool,
+    max_statements: Option<usize>,
+    max_call_depth: Option<usize>,
+}
+
+impl InterpreterBuilder {
+    pub fn new() -> Self {
+        InterpreterBuilder::default()
+    }
+
+    pub fn input(mut self, input: Box<dyn BufRead>) -> Self {
+        self.input = Some(input);
+        self
+    }
+
+    pub fn output(mut self, output: Box<dyn Write>) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    pub fn diagnostics(mut self, diagnostics: Box<dyn Write>) -> Self {
+        self.diagnostics = Some(diagnostics);
+        self
+    }
+
+    // See `Interpreter::strict`. The permissive modes below still apply on
+    // top of it.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    pub fn nil_arithmetic(mut self, nil_arithmetic: bool) -> Self {
+        self.nil_arithmetic = nil_arithmetic;
+        self
+    }
+
+    pub fn string_coercion(mut self, string_coercion: bool) -> Self {
+        self.string_coercion = string_coercion;
+        self
+    }
+
+    pub fn echo(mut self, echo: bool) -> Self {
+        self.echo = echo;
+        self
+    }
+
+    pub fn max_statements(mut self, max_statements: usize) -> Self {
+        self.max_statements = Some(max_statements);
+        self
+    }
+
+    pub fn max_call_depth(mut self, max_call_depth: usize) -> Self {
+        self.max_call_depth = Some(max_call_depth);
+        self
+    }
+
+    pub fn build(self) -> Interpreter {
+        let output = self.output.unwrap_or_else(|| Box::new(io::stdout()));
+        let mut interpreter = match self.input {
+            Some(input) => Interpreter::with_io(input, output),
+            None => Interpreter::with_output(output),
+        };
+        interpreter.set_strict(self.strict);
+        interpreter.set_nil_arithmetic(self.nil_arithmetic);
+        interpreter.set_string_coercion(self.string_coercion);
+        interpreter.set_echo(self.echo);
+        interpreter.set_max_statements(self.max_statements);
+        if let Some(max_call_depth) = self.max_call_depth {
+            interpreter.set_max_call_depth(max_call_depth);
+        }
+        if let Some(diagnostics) = self.diagnostics {
+            interpreter.set_diagnostics(diagnostics);
+        }
+        interpreter
+    }
+}
+
+// See `Interpreter::set_max_call_depth`.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+// How a statement finished. Anything other than `Normal` unwinds through the
+// enclosing statements until something handles it.
+#[derive(Debug)]
+pub(crate) enum ExecResult {
+    Normal,
+    Break,
+    Continue,
+    Return(Literal),
+    // A `return` of a call to the running function, with its arguments.
+    TailCall(Vec<Literal>),
+}
+
 #[derive(Debug, Clone)]
 pub struct RuntimeError {
     pub token: Token,
@@ -298,6 +1157,477 @@ impl error::Error for RuntimeError {}
 
 impl fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}, {}", self.token.to_string(), self.message)
+        write!(f, "{}, {}", self.token, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Lox;
+
+    // A `Write` the test keeps a handle to after giving it to the interpreter.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Runs `source` in a fresh interpreter, returning what it printed or the
+    // message of the runtime error that stopped it.
+    fn run(source: &str) -> Result<String, String> {
+        let output = Output::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        let statements = Lox::compile(source, false).expect("source should compile");
+        interpreter.interpret(statements).map_err(|e| e.message)?;
+        let printed = output.0.borrow().clone();
+        Ok(String::from_utf8(printed).unwrap())
+    }
+
+    // Runs `source` as one line typed at the REPL, which echoes.
+    fn run_repl(source: &str) -> String {
+        let output = Output::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        interpreter.set_echo(true);
+        let statements = Lox::compile(source, true).expect("source should compile");
+        interpreter
+            .interpret(statements)
+            .expect("source should run");
+        let printed = output.0.borrow().clone();
+        String::from_utf8(printed).unwrap()
+    }
+
+    #[test]
+    fn repl_echoes_a_bare_expression() {
+        assert_eq!(run_repl("\"hi\""), "\"hi\"\n");
+        assert_eq!(run_repl("2 + 2"), "4\n");
+        assert_eq!(run_repl("print \"hi\";"), "hi\n");
+    }
+
+    #[test]
+    fn scripts_do_not_echo_expression_statements() {
+        assert_eq!(run("var a = \"hi\"; a; a + \"!\";"), Ok("".to_owned()));
+    }
+
+    #[test]
+    fn repeating_a_string_is_capped() {
+        assert_eq!(run("print \"ab\" * 3;"), Ok("ababab\n".to_owned()));
+        assert_eq!(run("print 0 * \"ab\";"), Ok("\n".to_owned()));
+        let too_long = Err("Repeated string is too long.".to_owned());
+        assert_eq!(run("var s = \"ab\" * pow(10, 15);"), too_long);
+        assert_eq!(run("var s = \"ab\" * pow(10, 300);"), too_long);
+        assert_eq!(
+            run("var s = \"\" * pow(10, 15); print len(s);"),
+            Ok("0\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn budget_stops_recursion() {
+        let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+        interpreter.set_max_statements(Some(1000));
+        assert_eq!(
+            run_with(&mut interpreter, "fun f(n) { return f(n + 1); } f(0);"),
+            Err("Execution budget exceeded.".to_owned())
+        );
+        interpreter.set_max_statements(Some(40));
+        assert_eq!(
+            run_with(&mut interpreter, "fun g(n) { g(n + 1); } g(0);"),
+            Err("Execution budget exceeded.".to_owned())
+        );
+    }
+
+    #[test]
+    fn deep_recursion_is_a_stack_overflow_error() {
+        let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+        interpreter.set_max_call_depth(20);
+        let source = "fun f(n) { if (n == 0) return 0; return 1 + f(n - 1); }";
+        assert_eq!(run_with(&mut interpreter, source), Ok(()));
+        assert_eq!(run_with(&mut interpreter, "print f(19);"), Ok(()));
+        assert_eq!(
+            run_with(&mut interpreter, "print f(20);"),
+            Err("Stack overflow.".to_owned())
+        );
+        // Unwinding gives the depth back.
+        assert_eq!(run_with(&mut interpreter, "print f(19);"), Ok(()));
+    }
+
+    #[test]
+    fn reductions_accept_any_number_of_arguments() {
+        assert_eq!(
+            run("print min(3, 1, 2); print max(range(4)); print sum(1, 2, 3, 4);"),
+            Ok("1\n3\n10\n".to_owned())
+        );
+        assert_eq!(
+            run("min();"),
+            Err("Expected at least 1 arguments to 'min' but got 0.".to_owned())
+        );
+    }
+
+    #[test]
+    fn sets_dedup_and_combine() {
+        assert_eq!(
+            run("
+                var s = set(split(\"a,b,a,c,b\", \",\"));
+                print s;
+                print has(s, \"b\");
+                print has(s, \"d\");
+                add(s, \"d\");
+                print remove(s, \"a\");
+                print union(s, set(split(\"x,b\", \",\")));
+                print intersection(s, set(split(\"d,c,z\", \",\")));
+            "),
+            Ok("{\"a\", \"b\", \"c\"}\ntrue\nfalse\ntrue\n{\"b\", \"c\", \"d\", \"x\"}\n{\"c\", \"d\"}\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn set_members_that_are_lists_use_identity() {
+        assert_eq!(
+            run("
+                var a = range(2);
+                var s = set();
+                add(s, a);
+                add(s, a);
+                add(s, range(2));
+                print s;
+                print has(s, a);
+            "),
+            Ok("{[0, 1], [0, 1]}\ntrue\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn strict_mode_rejects_what_permissive_mode_allows() {
+        let source = "if (1) print \"yes\";";
+        assert_eq!(run(source), Ok("yes\n".to_owned()));
+        let output = Output::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output)).strict();
+        let error = interpreter
+            .interpret(Lox::compile(source, false).unwrap())
+            .unwrap_err();
+        assert_eq!(error.message, "Condition must be a boolean.");
+    }
+
+    #[test]
+    fn string_coercion_stringifies_the_other_operand() {
+        let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+        let program = "var s = \"n: \" + 1.5; s = 1 + \"\" + nil + true;";
+        assert_eq!(
+            run_with(&mut interpreter, program),
+            Err("Operands must be either two numbers or two strings.".to_owned())
+        );
+        let output = Output::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        interpreter.set_string_coercion(true);
+        let program = "print \"n: \" + 1.5; print 1 + \"\" + nil + true; print 1 + 2 + \"!\";";
+        assert_eq!(run_with(&mut interpreter, program), Ok(()));
+        assert_eq!(
+            String::from_utf8(output.0.borrow().clone()).unwrap(),
+            "n: 1.5\n1niltrue\n3!\n"
+        );
+    }
+
+    #[test]
+    fn later_setters_override_strict_mode() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone())).strict();
+        interpreter.set_string_coercion(true);
+        interpreter
+            .interpret(Lox::compile("if (true) print \"n: \" + 1;", false).unwrap())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output.0.borrow().clone()).unwrap(),
+            "n: 1\n"
+        );
+    }
+
+    #[test]
+    fn builder_applies_its_settings() {
+        let output = Output::default();
+        let mut interpreter = InterpreterBuilder::new()
+            .input(Box::new(io::Cursor::new("typed\n")))
+            .output(Box::new(output.clone()))
+            .string_coercion(true)
+            .max_statements(50)
+            .build();
+        let program = "print input() + 1; while (true) {}";
+        let error = interpreter
+            .interpret(Lox::compile(program, false).unwrap())
+            .unwrap_err();
+        assert_eq!(error.message, "Execution budget exceeded.");
+        assert_eq!(
+            String::from_utf8(output.0.borrow().clone()).unwrap(),
+            "typed1\n"
+        );
+    }
+
+    #[test]
+    fn collections_quote_strings_and_nest() {
+        assert_eq!(
+            run("print split(\"a,b\", \",\"); print set(range(2)); print \"a\";"),
+            Ok("[\"a\", \"b\"]\n{0, 1}\na\n".to_owned())
+        );
+        let inner = Literal::List(Rc::new(RefCell::new(vec![
+            Literal::Number(3.0),
+            Literal::Nil,
+        ])));
+        let outer = Literal::List(Rc::new(RefCell::new(vec![
+            Literal::String("1".to_owned()),
+            inner,
+        ])));
+        assert_eq!(Interpreter::stringify(&outer), "[\"1\", [3, nil]]");
+    }
+
+    #[test]
+    fn self_referential_collections_print() {
+        assert_eq!(
+            run("var s = set(); add(s, 1); add(s, s); print s;"),
+            Ok("{1, {...}}\n".to_owned())
+        );
+        let list = Rc::new(RefCell::new(vec![Literal::Number(1.0)]));
+        list.borrow_mut().push(Literal::List(Rc::clone(&list)));
+        let value = Literal::List(Rc::clone(&list));
+        assert_eq!(Interpreter::stringify(&value), "[1, [...]]");
+        // Break the cycle so the list is freed.
+        list.borrow_mut().clear();
+    }
+
+    fn run_with(interpreter: &mut Interpreter, source: &str) -> Result<(), String> {
+        let statements = Lox::compile(source, false).expect("source should compile");
+        interpreter.interpret(statements).map_err(|e| e.message)
+    }
+
+    #[test]
+    fn numeric_conditions_are_truthy_by_default() {
+        assert_eq!(
+            run("var n = 2; while (n) { print n; n = n - 1 == 0 ? nil : n - 1; }"),
+            Ok("2\n1\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn strict_conditions_reject_numeric_conditions() {
+        for source in &["if (1) {}", "while (0) {}", "for (; 1;) {}"] {
+            let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+            interpreter.set_strict_conditions(true);
+            assert_eq!(
+                run_with(&mut interpreter, source),
+                Err("Condition must be a boolean.".to_owned())
+            );
+        }
+    }
+
+    #[test]
+    fn comparisons_take_two_numbers_or_two_strings() {
+        assert_eq!(
+            run("print \"apple\" < \"banana\"; print \"b\" >= \"abc\"; print \"\" < \"a\";"),
+            Ok("true\ntrue\ntrue\n".to_owned())
+        );
+        for op in &["<", "<=", ">", ">="] {
+            assert_eq!(
+                run(&format!("var a = \"1\"; print a {} 2;", op)),
+                Err("Operands must be two numbers or two strings.".to_owned()),
+                "{}",
+                op
+            );
+        }
+    }
+
+    #[test]
+    fn nil_arithmetic_is_an_error_by_default() {
+        assert_eq!(
+            run("print nil + 5;"),
+            Err("Operands must be either two numbers or two strings.".to_owned())
+        );
+        assert_eq!(
+            run("print 2 - nil;"),
+            Err("Operands must be numbers.".to_owned())
+        );
+    }
+
+    #[test]
+    fn nil_arithmetic_propagates_nil() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        interpreter.set_nil_arithmetic(true);
+        run_with(
+            &mut interpreter,
+            "print nil + 5; print 2 * nil; print (nil - 1) / 2;",
+        )
+        .unwrap();
+        let printed = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(printed, "nil\nnil\nnil\n");
+    }
+
+    #[test]
+    fn equality_works_across_types() {
+        assert_eq!(
+            run("
+                print 1 == 1;
+                print nil == nil;
+                print nil == false;
+                print \"a\" == \"a\";
+                print 1 != \"1\";
+                print clock == clock;
+                class A {}
+                print A() == A();
+                var list = range(1);
+                print list == list;
+                print list == range(1);
+            "),
+            Ok("true\ntrue\nfalse\ntrue\ntrue\ntrue\nfalse\ntrue\nfalse\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn if_runs_one_branch_by_truthiness() {
+        assert_eq!(
+            run("
+                if (0) print \"zero\"; else print \"no\";
+                if (\"\") print \"empty\";
+                if (nil) print \"nil\"; else print \"else\";
+                if (false) print \"skipped\";
+            "),
+            Ok("zero\nempty\nelse\n".to_owned())
+        );
+        assert_eq!(
+            run("if (-\"a\") print 1; else print 2;"),
+            Err("Invalid negation operand.".to_owned())
+        );
+    }
+
+    #[test]
+    fn while_and_for_loop_until_the_condition_fails() {
+        assert_eq!(
+            run("
+                var i = 3;
+                while (i > 0) { print i; i = i - 1; }
+                for (var j = 0; j < 2; j = j + 1) print j;
+                while (false) print \"never\";
+            "),
+            Ok("3\n2\n1\n0\n1\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn errors_abort_a_loop() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        let result = run_with(
+            &mut interpreter,
+            "var i = 0; while (true) { print i; i = i + 1; if (i == 2) i = i + nil; }",
+        );
+        assert_eq!(
+            result,
+            Err("Operands must be either two numbers or two strings.".to_owned())
+        );
+        assert_eq!(output.0.borrow().as_slice(), b"0\n1\n");
+        assert_eq!(
+            run("var i = 0; while (i < -\"a\") i = i + 1;"),
+            Err("Invalid negation operand.".to_owned())
+        );
+    }
+
+    #[test]
+    fn blocks_scope_their_variables() {
+        assert_eq!(
+            run("
+                var a = \"outer\";
+                var b = \"outer\";
+                {
+                    var a = \"inner\";
+                    b = \"assigned\";
+                    { print a; }
+                }
+                print a;
+                print b;
+            "),
+            Ok("inner\nouter\nassigned\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn an_error_in_a_block_restores_the_scope() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        assert!(run_with(&mut interpreter, "var a = 1; { var a = 2; a = a + nil; }").is_err());
+        run_with(&mut interpreter, "print a;").unwrap();
+        assert_eq!(output.0.borrow().as_slice(), b"1\n");
+    }
+
+    #[test]
+    fn logical_operators_short_circuit_and_return_an_operand() {
+        assert_eq!(
+            run("
+                print nil or \"default\";
+                print \"set\" or \"default\";
+                print nil and \"unreached\";
+                print 1 and 2;
+                var calls = 0;
+                fun touch() { calls = calls + 1; return true; }
+                true or touch();
+                false and touch();
+                print calls;
+            "),
+            Ok("default\nset\nnil\n2\n0\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn any_zero_divisor_is_an_error() {
+        assert_eq!(
+            run("var zero = 0; print 1 / zero;"),
+            Err("Division by zero.".to_owned())
+        );
+        assert_eq!(
+            run("var zero = -0; print 0 / zero;"),
+            Err("Division by zero.".to_owned())
+        );
+        assert_eq!(run("var x = 1; print x / 4;"), Ok("0.25\n".to_owned()));
+    }
+
+    #[test]
+    fn print_writes_strings_unquoted() {
+        assert_eq!(
+            run("print \"hi\"; print \"nil\"; print nil; print 1.5; print true;"),
+            Ok("hi\nnil\nnil\n1.5\ntrue\n".to_owned())
+        );
+        assert_eq!(
+            Interpreter::stringify(&Literal::String("a b".to_owned())),
+            "a b"
+        );
+    }
+
+    #[test]
+    fn numbers_print_without_mangled_digits() {
+        let cases = vec![
+            (1e21, "1000000000000000000000"),
+            (0.5, "0.5"),
+            (-0.0, "-0"),
+            (100.0, "100"),
+            (0.1 + 0.2, "0.30000000000000004"),
+            (1e-7, "0.0000001"),
+        ];
+        for (n, printed) in cases {
+            assert_eq!(Interpreter::stringify(&Literal::Number(n)), printed);
+        }
+    }
+
+    #[test]
+    fn output_goes_to_the_injected_writer() {
+        let output = Output::default();
+        let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+        run_with(&mut interpreter, "print 1; print \"two\";").unwrap();
+        run_with(&mut interpreter, "print 3;").unwrap();
+        assert_eq!(output.0.borrow().as_slice(), b"1\ntwo\n3\n");
     }
 }
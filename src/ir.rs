@@ -0,0 +1,321 @@
+use crate::ast::{Expr, Stmt};
+use crate::parser::ParseError;
+use crate::token::{Literal, Token, TokenType};
+use std::fmt;
+
+// A flat, stack-based instruction list lowered from the AST. Jump targets are
+// absolute indices into the instruction list. Conditional jumps leave the
+// condition on the stack, so each branch starts with an explicit `Pop`.
+// `Condition` checks an `if` or `while` condition before it's tested, `Loop`
+// is a `while` loop's jump back to its start, and `PopExpr` ends an expression
+// statement; each carries what the interpreter's modes need at that point.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Constant(Literal),
+    Unary(Token),
+    Binary(Token),
+    DefineVar(String),
+    GetVar(Token),
+    SetVar(Token),
+    Pop,
+    PopExpr,
+    Print,
+    PushScope,
+    PopScope,
+    Jump(usize),
+    JumpIfFalse(usize),
+    Condition(Token),
+    Loop(Token, usize),
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Constant(literal) => match literal {
+                Literal::String(s) => write!(f, "CONSTANT \"{}\"", s),
+                Literal::Number(n) => write!(f, "CONSTANT {}", n),
+                Literal::Bool(b) => write!(f, "CONSTANT {}", b),
+                Literal::Nil => write!(f, "CONSTANT nil"),
+                Literal::Callable(c) => write!(f, "CONSTANT {}", c),
+                Literal::Class(c) => write!(f, "CONSTANT {}", c),
+                Literal::Instance(i) => write!(f, "CONSTANT {}", i.borrow()),
+                Literal::List(l) => write!(f, "CONSTANT {:?}", l.borrow()),
+                Literal::Set(s) => write!(f, "CONSTANT {:?}", s.borrow()),
+                Literal::Bytes(b) => write!(f, "CONSTANT {:?}", b),
+            },
+            Instruction::Unary(op) => write!(f, "UNARY {}", op.lexeme),
+            Instruction::Binary(op) => write!(f, "BINARY {}", op.lexeme),
+            Instruction::DefineVar(name) => write!(f, "DEFINE_VAR {}", name),
+            Instruction::GetVar(name) => write!(f, "GET_VAR {}", name.lexeme),
+            Instruction::SetVar(name) => write!(f, "SET_VAR {}", name.lexeme),
+            Instruction::Pop => write!(f, "POP"),
+            Instruction::PopExpr => write!(f, "POP_EXPR"),
+            Instruction::Print => write!(f, "PRINT"),
+            Instruction::PushScope => write!(f, "PUSH_SCOPE"),
+            Instruction::PopScope => write!(f, "POP_SCOPE"),
+            Instruction::Jump(target) => write!(f, "JUMP {:04}", target),
+            Instruction::JumpIfFalse(target) => write!(f, "JUMP_IF_FALSE {:04}", target),
+            Instruction::Condition(_) => write!(f, "CONDITION"),
+            Instruction::Loop(_, target) => write!(f, "LOOP {:04}", target),
+        }
+    }
+}
+
+// Fails on the first construct the VM has no instructions for.
+pub fn lower(statements: &[Box<Stmt>]) -> Result<Vec<Instruction>, ParseError> {
+    let mut lowerer = Lowerer {
+        code: Vec::new(),
+        error: None,
+    };
+    for statement in statements {
+        lowerer.statement(statement);
+    }
+    match lowerer.error {
+        Some(error) => Err(error),
+        None => Ok(lowerer.code),
+    }
+}
+
+pub fn disassemble(code: &[Instruction]) -> String {
+    code.iter()
+        .enumerate()
+        .map(|(offset, instruction)| format!("{:04} {}\n", offset, instruction))
+        .collect()
+}
+
+struct Lowerer {
+    code: Vec<Instruction>,
+    error: Option<ParseError>,
+}
+
+impl Lowerer {
+    fn statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Expr(expr) => {
+                self.expression(expr);
+                self.emit(Instruction::PopExpr);
+            }
+            Stmt::Print(expr) => {
+                self.expression(expr);
+                self.emit(Instruction::Print);
+            }
+            Stmt::Var(name, initializer) => {
+                match initializer.as_ref() {
+                    Some(e) => self.expression(e),
+                    None => self.emit(Instruction::Constant(Literal::Nil)),
+                }
+                self.emit(Instruction::DefineVar(name.lexeme.clone()));
+            }
+            Stmt::Block(statements) => {
+                self.emit(Instruction::PushScope);
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.emit(Instruction::PopScope);
+            }
+            Stmt::If(keyword, condition, then_branch, else_branch) => {
+                self.expression(condition);
+                self.emit(Instruction::Condition(keyword.clone()));
+                let then_jump = self.emit_jump(Instruction::JumpIfFalse(0));
+                self.emit(Instruction::Pop);
+                self.statement(then_branch);
+                let else_jump = self.emit_jump(Instruction::Jump(0));
+                self.patch_jump(then_jump);
+                self.emit(Instruction::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(else_jump);
+            }
+            Stmt::While(keyword, condition, body, increment) => {
+                let loop_start = self.code.len();
+                self.expression(condition);
+                self.emit(Instruction::Condition(keyword.clone()));
+                let exit_jump = self.emit_jump(Instruction::JumpIfFalse(0));
+                self.emit(Instruction::Pop);
+                self.statement(body);
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                    self.emit(Instruction::Pop);
+                }
+                self.emit(Instruction::Loop(keyword.clone(), loop_start));
+                self.patch_jump(exit_jump);
+                self.emit(Instruction::Pop);
+            }
+            Stmt::ForIn(keyword, _, _, _) => {
+                self.unsupported(keyword, "Lists are not supported by the VM.")
+            }
+            Stmt::Function(name, _, _) => {
+                self.unsupported(name, "Functions are not supported by the VM.")
+            }
+            Stmt::Class(name, _, _) => {
+                self.unsupported(name, "Classes are not supported by the VM.")
+            }
+            Stmt::Break(keyword) | Stmt::Continue(keyword) => {
+                self.unsupported(keyword, "Loop control is not supported by the VM.")
+            }
+            Stmt::Return(keyword, _) => {
+                self.unsupported(keyword, "Functions are not supported by the VM.")
+            }
+        }
+    }
+
+    fn unsupported(&mut self, token: &Token, message: &str) {
+        if self.error.is_none() {
+            self.error = Some(ParseError::new(token.clone(), message.to_owned()));
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal(literal) => self.emit(Instruction::Constant(literal.clone())),
+            Expr::Grouping(e) => self.expression(e),
+            Expr::Unary(op, e) => {
+                self.expression(e);
+                self.emit(Instruction::Unary(op.clone()));
+            }
+            Expr::Binary(lhs, op, rhs) => {
+                self.expression(lhs);
+                self.expression(rhs);
+                self.emit(Instruction::Binary(op.clone()));
+            }
+            Expr::Logical(lhs, op, rhs) => {
+                self.expression(lhs);
+                if op.token_type == TokenType::Or {
+                    let else_jump = self.emit_jump(Instruction::JumpIfFalse(0));
+                    let end_jump = self.emit_jump(Instruction::Jump(0));
+                    self.patch_jump(else_jump);
+                    self.emit(Instruction::Pop);
+                    self.expression(rhs);
+                    self.patch_jump(end_jump);
+                } else {
+                    let end_jump = self.emit_jump(Instruction::JumpIfFalse(0));
+                    self.emit(Instruction::Pop);
+                    self.expression(rhs);
+                    self.patch_jump(end_jump);
+                }
+            }
+            Expr::Ternary(condition, then_branch, else_branch) => {
+                self.expression(condition);
+                let else_jump = self.emit_jump(Instruction::JumpIfFalse(0));
+                self.emit(Instruction::Pop);
+                self.expression(then_branch);
+                let end_jump = self.emit_jump(Instruction::Jump(0));
+                self.patch_jump(else_jump);
+                self.emit(Instruction::Pop);
+                self.expression(else_branch);
+                self.patch_jump(end_jump);
+            }
+            Expr::Variable(name, _) => self.emit(Instruction::GetVar(name.clone())),
+            Expr::Assignment(name, value, _) => {
+                self.expression(value);
+                self.emit(Instruction::SetVar(name.clone()));
+            }
+            Expr::Call(_, paren, _) => {
+                self.unsupported(paren, "Calls are not supported by the VM.")
+            }
+            Expr::Get(_, name) | Expr::Set(_, name, _) => {
+                self.unsupported(name, "Properties are not supported by the VM.")
+            }
+            Expr::Index(_, bracket, _) => {
+                self.unsupported(bracket, "Indexing is not supported by the VM.")
+            }
+            Expr::This(keyword, _) | Expr::Super(keyword, _, _) => {
+                self.unsupported(keyword, "Classes are not supported by the VM.")
+            }
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.code.push(instruction);
+    }
+
+    fn emit_jump(&mut self, instruction: Instruction) -> usize {
+        self.emit(instruction);
+        self.code.len() - 1
+    }
+
+    // Points a previously emitted jump at the next instruction to be emitted.
+    fn patch_jump(&mut self, offset: usize) {
+        let target = self.code.len();
+        match &mut self.code[offset] {
+            Instruction::Jump(t) | Instruction::JumpIfFalse(t) => *t = target,
+            _ => unreachable!("patched instruction is not a jump"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Lox;
+
+    fn dump(source: &str) -> String {
+        let statements = Lox::compile(source, false).expect("source should compile");
+        disassemble(&lower(&statements).expect("source should lower"))
+    }
+
+    #[test]
+    fn lowers_arithmetic() {
+        assert_eq!(
+            dump("var x = 4; print (x + 2) * -x;"),
+            "0000 CONSTANT 4\n\
+             0001 DEFINE_VAR x\n\
+             0002 GET_VAR x\n\
+             0003 CONSTANT 2\n\
+             0004 BINARY +\n\
+             0005 GET_VAR x\n\
+             0006 UNARY -\n\
+             0007 BINARY *\n\
+             0008 PRINT\n"
+        );
+    }
+
+    #[test]
+    fn lowers_if_else_with_jumps() {
+        assert_eq!(
+            dump("var x = true; if (x) print 1; else print 2;"),
+            "0000 CONSTANT true\n\
+             0001 DEFINE_VAR x\n\
+             0002 GET_VAR x\n\
+             0003 CONDITION\n\
+             0004 JUMP_IF_FALSE 0009\n\
+             0005 POP\n\
+             0006 CONSTANT 1\n\
+             0007 PRINT\n\
+             0008 JUMP 0012\n\
+             0009 POP\n\
+             0010 CONSTANT 2\n\
+             0011 PRINT\n"
+        );
+    }
+
+    #[test]
+    fn lowers_while_with_a_loop_back() {
+        assert_eq!(
+            dump("var i = 0; while (i < 2) i = i + 1;"),
+            "0000 CONSTANT 0\n\
+             0001 DEFINE_VAR i\n\
+             0002 GET_VAR i\n\
+             0003 CONSTANT 2\n\
+             0004 BINARY <\n\
+             0005 CONDITION\n\
+             0006 JUMP_IF_FALSE 0014\n\
+             0007 POP\n\
+             0008 GET_VAR i\n\
+             0009 CONSTANT 1\n\
+             0010 BINARY +\n\
+             0011 SET_VAR i\n\
+             0012 POP_EXPR\n\
+             0013 LOOP 0002\n\
+             0014 POP\n"
+        );
+    }
+
+    #[test]
+    fn rejects_what_the_vm_cannot_run() {
+        let statements = Lox::compile("fun f() {}", false).unwrap();
+        let error = lower(&statements).unwrap_err();
+        assert_eq!(error.message, "Functions are not supported by the VM.");
+    }
+}
@@ -1,45 +1,139 @@
 pub mod ast;
+pub mod callable;
+pub mod class;
 pub mod environment;
 pub mod interpreter;
+pub mod ir;
+pub mod natives;
+pub mod optimizer;
 pub mod parser;
+pub mod resolver;
 pub mod scanner;
+pub mod set;
 pub mod token;
 
+use ast::Stmt;
 use interpreter::{Interpreter, RuntimeError};
 use parser::{ParseError, Parser};
-use scanner::Scanner;
+use resolver::Resolver;
+use scanner::{ScanError, Scanner};
 use std::env;
 use std::fs;
-use std::io;
+use std::io::{self, BufRead, Write};
 use std::process;
 use token::{Token, TokenType};
 
 static mut HAD_ERROR: bool = false;
 static mut HAD_RUNTIME_ERROR: bool = false;
 
+// Any error raised while running a piece of source, from whichever stage
+// found it.
+#[derive(Debug)]
+pub enum LoxError {
+    Scan(ScanError),
+    Parse(ParseError),
+    Runtime(RuntimeError),
+}
+
 #[derive(Default)]
-pub struct Lox {}
+pub struct Lox {
+    use_vm: bool,
+    interpreter: Interpreter,
+}
 
 impl Lox {
     pub fn new() -> Self {
-        Lox {}
+        Lox::with_interpreter(Interpreter::new())
+    }
+
+    // For embedding: runs code in `interpreter`, so the caller chooses where
+    // output goes and which modes are on.
+    pub fn with_interpreter(interpreter: Interpreter) -> Self {
+        Lox {
+            use_vm: false,
+            interpreter,
+        }
+    }
+
+    // Runs code on the IR VM instead of the tree-walker, as `--vm` does.
+    pub fn set_vm(&mut self, use_vm: bool) {
+        self.use_vm = use_vm;
     }
 
     pub fn init(&mut self) {
-        let args: Vec<String> = env::args().skip(1).collect();
-        if args.len() > 1 {
-            println!("Usage: rlox [script]");
-            process::exit(64);
-        } else if args.len() == 1 {
-            self.run_file(&args[0]);
-        } else {
-            self.run_prompt();
+        let mut args = env::args().skip(1);
+        let mut script: Option<String> = None;
+        let mut dump_ir = false;
+        let mut dump_tokens = false;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--tokens" => dump_tokens = true,
+                "--dump-ir" => dump_ir = true,
+                "--vm" => self.use_vm = true,
+                "--strict" => self.interpreter.set_strict(true),
+                "--stmt-limit" => match args.next().and_then(|n| n.parse().ok()) {
+                    Some(limit) => self.interpreter.set_max_statements(Some(limit)),
+                    None => Lox::usage(),
+                },
+                _ if script.is_none() && !arg.starts_with("--") => script = Some(arg),
+                _ => Lox::usage(),
+            }
+        }
+        match script {
+            Some(path) if dump_tokens => Lox::dump_tokens(&path),
+            Some(path) if dump_ir => self.dump_ir(&path),
+            Some(path) => self.run_file(&path),
+            None if dump_ir || dump_tokens => Lox::usage(),
+            None => self.run_prompt(),
+        }
+    }
+
+    fn usage() -> ! {
+        println!("Usage: rlox [--tokens | --dump-ir | --vm] [--strict] [--stmt-limit N] [script]");
+        process::exit(64);
+    }
+
+    // Prints each token the scanner produces, one per line, without parsing.
+    fn dump_tokens(path: &str) {
+        let file = fs::read_to_string(path).unwrap();
+        let (tokens, errors) = Scanner::new(file.clone()).scan_partial();
+        print!("{}", Lox::token_listing(&tokens));
+        if !errors.is_empty() {
+            Lox::report_errors(&file, errors.into_iter().map(LoxError::Scan).collect());
+            process::exit(65);
+        }
+    }
+
+    fn token_listing(tokens: &[Token]) -> String {
+        tokens
+            .iter()
+            .map(|token| {
+                format!(
+                    "[line {}, col {}] {}\n",
+                    token.line,
+                    token.column,
+                    token.to_string().trim_end()
+                )
+            })
+            .collect()
+    }
+
+    fn dump_ir(&mut self, path: &str) {
+        let file = fs::read_to_string(path).unwrap();
+        let code = Lox::compile(&file, false)
+            .and_then(|statements| ir::lower(&statements).map_err(|e| vec![LoxError::Parse(e)]));
+        match code {
+            Ok(code) => print!("{}", ir::disassemble(&code)),
+            Err(errors) => {
+                Lox::report_errors(&file, errors);
+                process::exit(65);
+            }
         }
     }
 
-    fn run_file(&mut self, path: &String) {
+    fn run_file(&mut self, path: &str) {
         let file = fs::read_to_string(path).unwrap();
-        self.run(&file);
+        self.run(&file, false);
         unsafe {
             if HAD_ERROR {
                 process::exit(65);
@@ -51,64 +145,284 @@ impl Lox {
     }
 
     fn run_prompt(&mut self) {
+        let stdin = io::stdin();
+        self.prompt(stdin.lock());
+    }
+
+    // The REPL loop, reading lines from `input` until it runs out.
+    fn prompt<R: BufRead>(&mut self, mut input: R) {
+        self.interpreter.set_echo(true);
+        let mut source = String::new();
         loop {
-            println!("> ");
+            print!("{}", if source.is_empty() { "> " } else { "... " });
+            io::stdout().flush().unwrap();
             let mut line = String::new();
-            io::stdin().read_line(&mut line).unwrap();
-            if line.is_empty() {
+            // Only end of input (Ctrl-D) reads nothing at all; a blank line
+            // still contains its newline.
+            if input.read_line(&mut line).unwrap() == 0 {
+                println!();
                 break;
             }
-            self.run(&line);
+            source.push_str(&line);
+            // Keep reading while the input so far just stops too early, e.g.
+            // inside a function body. A blank line submits it regardless, so
+            // a genuinely missing ';' or '}' still gets reported.
+            if !line.trim().is_empty() && Lox::is_incomplete(&source) {
+                continue;
+            }
+            self.run(&source, true);
+            source.clear();
             unsafe {
                 HAD_ERROR = false;
             }
         }
     }
 
-    fn run(&mut self, source: &String) {
-        let mut scanner: Scanner = Scanner::new(source.clone());
-        let tokens: Vec<Token> = scanner.scan_tokens();
-        let mut parser: Parser = Parser::new(tokens.clone());
-        let expression = parser.parse();
+    fn is_incomplete(source: &str) -> bool {
+        match Lox::compile(source, true) {
+            Ok(_) => false,
+            Err(errors) => errors.iter().any(|error| match error {
+                LoxError::Scan(error) => error.unterminated,
+                LoxError::Parse(error) => error.token.token_type == TokenType::Eof,
+                LoxError::Runtime(_) => false,
+            }),
+        }
+    }
+
+    fn run(&mut self, source: &str, repl: bool) {
+        if let Err(errors) = self.execute(source, repl) {
+            Lox::report_errors(source, errors);
+        }
+    }
 
-        unsafe {
-            if HAD_ERROR {
-                return;
+    fn report_errors(source: &str, errors: Vec<LoxError>) {
+        for error in errors {
+            match error {
+                LoxError::Scan(error) => Lox::scan_error(source, error),
+                LoxError::Parse(error) => Lox::parse_error(source, error),
+                LoxError::Runtime(error) => Lox::runtime_error(error),
+            }
+        }
+    }
+
+    // Runs `source` as a script without printing errors or exiting, for
+    // embedding. Every scan and parse error is returned; if there are none the
+    // program runs, stopping at the first runtime error. State such as
+    // globals carries over between calls.
+    pub fn run_source(&mut self, source: &str) -> Result<(), Vec<LoxError>> {
+        self.execute(source, false)
+    }
+
+    fn execute(&mut self, source: &str, repl: bool) -> Result<(), Vec<LoxError>> {
+        let statements = Lox::compile(source, repl)?;
+        if self.use_vm {
+            let code = ir::lower(&statements).map_err(|e| vec![LoxError::Parse(e)])?;
+            self.interpreter.run_vm(&code)
+        } else {
+            self.interpreter.interpret(statements)
+        }
+        .map_err(|e| vec![LoxError::Runtime(e)])
+    }
+
+    // Scans, parses and resolves `source` without running it, returning every
+    // error found. With `repl`, a lone expression without a trailing ';' is
+    // accepted; otherwise side-effect-free expression statements are dropped.
+    // Constant subexpressions are folded either way.
+    pub fn compile(source: &str, repl: bool) -> Result<Vec<Box<Stmt>>, Vec<LoxError>> {
+        let (tokens, scan_errors) = Scanner::new(source.to_owned()).scan_partial();
+        let mut parser: Parser = Parser::new(tokens);
+        let (mut statements, parse_errors) = if repl {
+            parser.parse_repl()
+        } else {
+            parser.parse()
+        };
+
+        let mut errors: Vec<LoxError> = scan_errors.into_iter().map(LoxError::Scan).collect();
+        errors.extend(parse_errors.into_iter().map(LoxError::Parse));
+        if errors.is_empty() {
+            errors.extend(
+                Resolver::new()
+                    .resolve(&mut statements)
+                    .into_iter()
+                    .map(LoxError::Parse),
+            );
+        }
+        if errors.is_empty() {
+            optimizer::fold_constants(&mut statements);
+            if !repl {
+                optimizer::strip_pure_expressions(&mut statements);
             }
+            Ok(statements)
+        } else {
+            Err(errors)
         }
-        let mut interpreter = Interpreter::new();
-        interpreter.interpret(expression);
     }
 
-    fn error(line: i32, message: String) {
-        Lox::report(line, "".to_owned(), message);
+    // `source` is the text the error was found in, used to quote the
+    // offending line. It may be empty if the text isn't at hand.
+    fn scan_error(source: &str, error: ScanError) {
+        Lox::report(
+            source,
+            error.line,
+            error.column,
+            "".to_owned(),
+            error.message,
+        );
     }
 
     fn runtime_error(error: RuntimeError) {
-        println!("{} \n [line {}]", error.message, error.token.line);
+        println!(
+            "{} \n [line {}, col {}]",
+            error.message, error.token.line, error.token.column
+        );
         unsafe { HAD_RUNTIME_ERROR = true }
     }
 
-    fn parse_error(error: ParseError) {
+    fn parse_error(source: &str, error: ParseError) {
         match error.token.token_type {
-            TokenType::Eof => Lox::report(error.token.line, " at end".to_owned(), error.message),
+            TokenType::Eof => Lox::report(
+                source,
+                error.token.line,
+                error.token.column,
+                " at end".to_owned(),
+                error.message,
+            ),
             _ => Lox::report(
+                source,
                 error.token.line,
+                error.token.column,
                 format!("at, {}", error.token.lexeme),
                 error.message,
             ),
         }
     }
 
-    fn report(line: i32, location: String, message: String) {
+    fn report(source: &str, line: i32, column: i32, location: String, message: String) {
         eprintln!(
-            "[line {line}] Error {location}: {message}",
+            "[line {line}, col {column}] Error {location}: {message}",
             line = line,
+            column = column,
             location = location,
             message = message
         );
+        eprint!("{}", Lox::quote_source(source, line, column));
         unsafe {
             HAD_ERROR = true;
         }
     }
+
+    // The source line an error points into with a caret under its column,
+    // e.g.
+    //
+    //     3 | print a +;
+    //       |          ^
+    //
+    // Tabs before the column are kept so the caret lines up however wide the
+    // terminal draws them. Empty if the line isn't in `source`.
+    fn quote_source(source: &str, line: i32, column: i32) -> String {
+        let text = match source.lines().nth((line - 1).max(0) as usize) {
+            Some(text) => text,
+            None => return String::new(),
+        };
+        let padding: String = text
+            .chars()
+            .take((column - 1).max(0) as usize)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let gutter = line.to_string().len();
+        format!(
+            "{:>width$} | {}\n{:>width$} | {}^\n",
+            line,
+            text,
+            "",
+            padding,
+            width = gutter
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn quotes_the_line_with_a_caret_under_the_column() {
+        assert_eq!(
+            Lox::quote_source("var a;\nprint a +;", 2, 10),
+            "2 | print a +;\n  |          ^\n"
+        );
+        assert_eq!(Lox::quote_source("\tx @;", 1, 4), "1 | \tx @;\n  | \t  ^\n");
+        let source = "\n".repeat(11) + "oops";
+        assert_eq!(Lox::quote_source(&source, 12, 1), "12 | oops\n   | ^\n");
+        assert_eq!(Lox::quote_source("", 1, 1), "");
+    }
+
+    // A `Write` the test keeps a handle to after giving it to the interpreter.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Feeds `input` to the REPL, returning what the programs printed.
+    fn repl(input: &str) -> String {
+        let output = Output::default();
+        let mut lox = Lox::with_interpreter(Interpreter::with_output(Box::new(output.clone())));
+        lox.prompt(input.as_bytes());
+        let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+        text
+    }
+
+    #[test]
+    fn repl_keeps_reading_after_blank_lines() {
+        assert_eq!(repl("\n\nvar a = 1;\n\n   \nprint a;\n"), "1\n");
+        assert_eq!(repl(""), "");
+    }
+
+    #[test]
+    fn repl_echoes_expression_results() {
+        assert_eq!(repl("1 + 1;\nvar a = 2;\na\nprint a;\n"), "2\n2\n2\n");
+    }
+
+    #[test]
+    fn input_that_stops_early_is_incomplete() {
+        assert!(Lox::is_incomplete("fun f() {"));
+        assert!(Lox::is_incomplete("print (1 +"));
+        assert!(Lox::is_incomplete("print \"abc"));
+        assert!(!Lox::is_incomplete("print 1;"));
+        assert!(!Lox::is_incomplete("print 1 +;"));
+        assert!(!Lox::is_incomplete("}"));
+    }
+
+    #[test]
+    fn repl_continues_a_statement_across_lines() {
+        assert_eq!(repl("fun f() {\n  return 2;\n}\nprint f();\n"), "2\n");
+        assert_eq!(repl("print (1 +\n2);\n"), "3\n");
+        // A blank line submits what's there, so the next line starts afresh.
+        assert_eq!(repl("print (1 +\n\nprint 4;\n"), "4\n");
+    }
+
+    #[test]
+    fn lists_each_token_with_its_position() {
+        let (tokens, errors) = Scanner::new("print 1;\n  x".to_owned()).scan_partial();
+        assert!(errors.is_empty());
+        assert_eq!(
+            Lox::token_listing(&tokens),
+            "[line 1, col 1] Print print Some(Nil)\n\
+             [line 1, col 7] Number 1 Some(Number(1.0))\n\
+             [line 1, col 8] Semicolon ; Some(Nil)\n\
+             [line 2, col 3] Identifier x Some(Nil)\n\
+             [line 2, col 4] Eof  None\n"
+        );
+    }
 }
@@ -0,0 +1,919 @@
+use crate::callable::MemoizedFunction;
+use crate::interpreter::Interpreter;
+use crate::set::LoxSet;
+use crate::token::Literal;
+use std::cell::RefCell;
+use std::io::BufRead;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The most elements a native will build into a list (or bytes into a string)
+// in one go, so a typo like `range(1e15)` fails cleanly instead of exhausting
+// memory.
+pub(crate) const MAX_LENGTH: usize = 10_000_000;
+
+// Seconds since the Unix epoch, with sub-second precision, for timing code.
+pub fn clock(_arguments: &[Literal]) -> Result<Literal, String> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(Literal::Number(elapsed.as_secs_f64()))
+}
+
+// The number of elements in a list or bytes, or of characters (Unicode
+// scalar values, not bytes) in a string.
+pub fn len(arguments: &[Literal]) -> Result<Literal, String> {
+    match &arguments[0] {
+        Literal::String(s) => Ok(Literal::Number(s.chars().count() as f64)),
+        Literal::List(l) => Ok(Literal::Number(l.borrow().len() as f64)),
+        Literal::Bytes(b) => Ok(Literal::Number(b.len() as f64)),
+        _ => Err("Argument to 'len' must be a string, a list or bytes.".to_owned()),
+    }
+}
+
+pub fn upper(arguments: &[Literal]) -> Result<Literal, String> {
+    let s = string_argument(&arguments[0], "upper")?;
+    Ok(Literal::String(s.to_uppercase()))
+}
+
+pub fn lower(arguments: &[Literal]) -> Result<Literal, String> {
+    let s = string_argument(&arguments[0], "lower")?;
+    Ok(Literal::String(s.to_lowercase()))
+}
+
+// Strips leading and trailing whitespace.
+pub fn trim(arguments: &[Literal]) -> Result<Literal, String> {
+    let s = string_argument(&arguments[0], "trim")?;
+    Ok(Literal::String(s.trim().to_owned()))
+}
+
+// Splits `s` on every occurrence of `separator`, or into single characters if
+// the separator is empty. A trailing separator leaves a trailing empty string.
+pub fn split(arguments: &[Literal]) -> Result<Literal, String> {
+    let s = string_argument(&arguments[0], "split")?;
+    let separator = string_argument(&arguments[1], "split")?;
+    let pieces: Vec<Literal> = if separator.is_empty() {
+        s.chars().map(|c| Literal::String(c.to_string())).collect()
+    } else {
+        s.split(separator)
+            .map(|piece| Literal::String(piece.to_owned()))
+            .collect()
+    };
+    Ok(Literal::List(Rc::new(RefCell::new(pieces))))
+}
+
+// `range(end)`, `range(start, end)` or `range(start, end, step)`: the numbers
+// from `start` (default 0) up to but not including `end`, `step` (default 1)
+// apart. A negative step counts down instead.
+pub fn range(arguments: &[Literal]) -> Result<Literal, String> {
+    let numbers = arguments
+        .iter()
+        .map(|argument| number_argument(argument, "range"))
+        .collect::<Result<Vec<f64>, String>>()?;
+    let (start, end, step) = match numbers[..] {
+        [end] => (0.0, end, 1.0),
+        [start, end] => (start, end, 1.0),
+        [start, end, step] => (start, end, step),
+        _ => unreachable!("arity is checked before the call"),
+    };
+    if numbers.iter().any(|n| !n.is_finite()) {
+        return Err("Arguments to 'range' must be finite.".to_owned());
+    }
+    if step == 0.0 {
+        return Err("Step of 'range' can't be zero.".to_owned());
+    }
+    // Working from the count rather than adding `step` repeatedly means a
+    // step too small to change a huge `start` can't loop forever.
+    let count = ((end - start) / step).ceil().max(0.0);
+    if count > MAX_LENGTH as f64 {
+        return Err("Range is too long.".to_owned());
+    }
+    let elements = (0..count as usize)
+        .map(|i| Literal::Number(start + i as f64 * step))
+        .collect();
+    Ok(Literal::List(Rc::new(RefCell::new(elements))))
+}
+
+// Whether a list has an element equal to `value`, using the same equality as
+// `==`, or whether a string contains `value` as a substring. There are no
+// maps yet, so those are the only collections.
+pub fn contains(arguments: &[Literal]) -> Result<Literal, String> {
+    match &arguments[0] {
+        Literal::List(l) => Ok(Literal::Bool(l.borrow().contains(&arguments[1]))),
+        Literal::String(s) => {
+            let substring = string_argument(&arguments[1], "contains")?;
+            Ok(Literal::Bool(s.contains(substring)))
+        }
+        _ => Err("First argument to 'contains' must be a list or a string.".to_owned()),
+    }
+}
+
+// `min(list)` or `min(a, b, ...)`: the smallest of some numbers. There's no
+// smallest of none, so an empty list is an error.
+pub fn min(arguments: &[Literal]) -> Result<Literal, String> {
+    let numbers = reduction_arguments(arguments, "min")?;
+    numbers
+        .into_iter()
+        .reduce(f64::min)
+        .map(Literal::Number)
+        .ok_or_else(|| "Can't take 'min' of an empty list.".to_owned())
+}
+
+// Like `min`, for the largest.
+pub fn max(arguments: &[Literal]) -> Result<Literal, String> {
+    let numbers = reduction_arguments(arguments, "max")?;
+    numbers
+        .into_iter()
+        .reduce(f64::max)
+        .map(Literal::Number)
+        .ok_or_else(|| "Can't take 'max' of an empty list.".to_owned())
+}
+
+// `sum(list)` or `sum(a, b, ...)`. The sum of an empty list is 0.
+pub fn sum(arguments: &[Literal]) -> Result<Literal, String> {
+    let numbers = reduction_arguments(arguments, "sum")?;
+    Ok(Literal::Number(numbers.into_iter().sum()))
+}
+
+// The numbers a reduction runs over: the elements of a lone list argument,
+// or else the arguments themselves.
+fn reduction_arguments(arguments: &[Literal], function: &str) -> Result<Vec<f64>, String> {
+    match arguments {
+        [Literal::List(l)] => l
+            .borrow()
+            .iter()
+            .map(|element| number_argument(element, function))
+            .collect(),
+        _ => arguments
+            .iter()
+            .map(|argument| number_argument(argument, function))
+            .collect(),
+    }
+}
+
+// `set()` is a new empty set and `set(list)` one holding each distinct
+// element of the list.
+pub fn set(arguments: &[Literal]) -> Result<Literal, String> {
+    let members = match arguments.first() {
+        None => LoxSet::new(),
+        Some(Literal::List(l)) => LoxSet::from_values(l.borrow().iter().cloned())?,
+        Some(_) => return Err("Argument to 'set' must be a list.".to_owned()),
+    };
+    Ok(Literal::Set(Rc::new(RefCell::new(members))))
+}
+
+// Adds `value` to a set, doing nothing if it's already a member.
+pub fn add(arguments: &[Literal]) -> Result<Literal, String> {
+    let s = set_argument(&arguments[0], "add")?;
+    s.borrow_mut().insert(arguments[1].clone())?;
+    Ok(Literal::Nil)
+}
+
+pub fn has(arguments: &[Literal]) -> Result<Literal, String> {
+    let s = set_argument(&arguments[0], "has")?;
+    let found = s.borrow().contains(&arguments[1]);
+    Ok(Literal::Bool(found))
+}
+
+// Removes `value` from a set, returning whether it was there.
+pub fn remove(arguments: &[Literal]) -> Result<Literal, String> {
+    let s = set_argument(&arguments[0], "remove")?;
+    let removed = s.borrow_mut().remove(&arguments[1]);
+    Ok(Literal::Bool(removed))
+}
+
+// A new set of the members of either set: those of `a` first, then those
+// only in `b`.
+pub fn union(arguments: &[Literal]) -> Result<Literal, String> {
+    let a = set_argument(&arguments[0], "union")?.borrow();
+    let b = set_argument(&arguments[1], "union")?.borrow();
+    let members = LoxSet::from_values(a.iter().chain(b.iter()).cloned())?;
+    Ok(Literal::Set(Rc::new(RefCell::new(members))))
+}
+
+// A new set of the members of `a` that are also in `b`.
+pub fn intersection(arguments: &[Literal]) -> Result<Literal, String> {
+    let a = set_argument(&arguments[0], "intersection")?.borrow();
+    let b = set_argument(&arguments[1], "intersection")?.borrow();
+    let members = LoxSet::from_values(a.iter().filter(|member| b.contains(member)).cloned())?;
+    Ok(Literal::Set(Rc::new(RefCell::new(members))))
+}
+
+// Wraps a function so repeated calls with the same arguments reuse the first
+// result. See `MemoizedFunction`.
+pub fn memoize(arguments: &[Literal]) -> Result<Literal, String> {
+    match &arguments[0] {
+        Literal::Callable(function) => {
+            let memoized = MemoizedFunction::new(Rc::clone(function));
+            Ok(Literal::Callable(Rc::new(memoized)))
+        }
+        _ => Err("Argument to 'memoize' must be a function.".to_owned()),
+    }
+}
+
+// Forgets every result a `memoize`d function has remembered.
+pub fn clear_cache(arguments: &[Literal]) -> Result<Literal, String> {
+    match &arguments[0] {
+        Literal::Callable(function) => match function.as_memoized() {
+            Some(memoized) => {
+                memoized.clear_cache();
+                Ok(Literal::Nil)
+            }
+            None => Err("Argument to 'clearCache' must be a memoized function.".to_owned()),
+        },
+        _ => Err("Argument to 'clearCache' must be a memoized function.".to_owned()),
+    }
+}
+
+// A string's UTF-8 encoding.
+pub fn bytes(arguments: &[Literal]) -> Result<Literal, String> {
+    let s = string_argument(&arguments[0], "bytes")?;
+    Ok(Literal::Bytes(Rc::new(s.as_bytes().to_vec())))
+}
+
+// The string whose UTF-8 encoding is the given bytes.
+pub fn from_bytes(arguments: &[Literal]) -> Result<Literal, String> {
+    let b = bytes_argument(&arguments[0], "fromBytes")?;
+    match String::from_utf8(b.to_vec()) {
+        Ok(s) => Ok(Literal::String(s)),
+        Err(_) => Err("Bytes passed to 'fromBytes' are not valid UTF-8.".to_owned()),
+    }
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Standard base64 (RFC 4648), padded with '='.
+pub fn base64_encode(arguments: &[Literal]) -> Result<Literal, String> {
+    let b = bytes_argument(&arguments[0], "base64Encode")?;
+    let mut encoded = String::with_capacity(b.len().div_ceil(3) * 4);
+    for chunk in b.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let sextet = (group >> (18 - 6 * i)) & 0x3f;
+                encoded.push(BASE64_ALPHABET[sextet as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    Ok(Literal::String(encoded))
+}
+
+// The inverse of `base64Encode`. The input must be padded to a multiple of
+// four characters, with no whitespace.
+pub fn base64_decode(arguments: &[Literal]) -> Result<Literal, String> {
+    let s = string_argument(&arguments[0], "base64Decode")?;
+    let invalid = || "Invalid base64 input.".to_owned();
+    let input = s.as_bytes();
+    if input.len() % 4 != 0 {
+        return Err(invalid());
+    }
+    let mut decoded = Vec::with_capacity(input.len() / 4 * 3);
+    for (n, chunk) in input.chunks(4).enumerate() {
+        let last = n == input.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return Err(invalid());
+        }
+        let mut group = 0u32;
+        for (i, c) in chunk[..4 - padding].iter().enumerate() {
+            let sextet = BASE64_ALPHABET
+                .iter()
+                .position(|a| a == c)
+                .ok_or_else(invalid)?;
+            group |= (sextet as u32) << (18 - 6 * i);
+        }
+        for i in 0..3 - padding {
+            decoded.push((group >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(Literal::Bytes(Rc::new(decoded)))
+}
+
+// Unlike `f64::sqrt`, a negative argument is an error rather than NaN.
+pub fn sqrt(arguments: &[Literal]) -> Result<Literal, String> {
+    let n = number_argument(&arguments[0], "sqrt")?;
+    if n < 0.0 {
+        return Err("Can't take the square root of a negative number.".to_owned());
+    }
+    Ok(Literal::Number(n.sqrt()))
+}
+
+pub fn abs(arguments: &[Literal]) -> Result<Literal, String> {
+    let n = number_argument(&arguments[0], "abs")?;
+    Ok(Literal::Number(n.abs()))
+}
+
+pub fn floor(arguments: &[Literal]) -> Result<Literal, String> {
+    let n = number_argument(&arguments[0], "floor")?;
+    Ok(Literal::Number(n.floor()))
+}
+
+pub fn ceil(arguments: &[Literal]) -> Result<Literal, String> {
+    let n = number_argument(&arguments[0], "ceil")?;
+    Ok(Literal::Number(n.ceil()))
+}
+
+pub fn pow(arguments: &[Literal]) -> Result<Literal, String> {
+    let base = number_argument(&arguments[0], "pow")?;
+    let exponent = number_argument(&arguments[1], "pow")?;
+    Ok(Literal::Number(base.powf(exponent)))
+}
+
+// Parses a number, ignoring surrounding whitespace.
+pub fn num(arguments: &[Literal]) -> Result<Literal, String> {
+    let s = string_argument(&arguments[0], "num")?;
+    s.trim()
+        .parse::<f64>()
+        .map(Literal::Number)
+        .map_err(|_| format!("Can't convert '{}' to a number.", s))
+}
+
+// Any value as `print` would show it.
+pub fn str(arguments: &[Literal]) -> Result<Literal, String> {
+    Ok(Literal::String(Interpreter::stringify(&arguments[0])))
+}
+
+pub fn type_of(arguments: &[Literal]) -> Result<Literal, String> {
+    let name = match &arguments[0] {
+        Literal::Number(_) => "number",
+        Literal::String(_) => "string",
+        Literal::Bool(_) => "bool",
+        Literal::Nil => "nil",
+        Literal::Callable(_) => "function",
+        Literal::Class(_) => "class",
+        Literal::Instance(_) => "instance",
+        Literal::List(_) => "list",
+        Literal::Set(_) => "set",
+        Literal::Bytes(_) => "bytes",
+    };
+    Ok(Literal::String(name.to_owned()))
+}
+
+// Fails with `message`, or a default, unless `condition` is truthy. The
+// failure is an ordinary runtime error, so a script exits with status 70.
+pub fn assert(arguments: &[Literal]) -> Result<Literal, String> {
+    if Interpreter::is_truthy(arguments[0].clone()) {
+        return Ok(Literal::Nil);
+    }
+    match arguments.get(1) {
+        Some(message) => Err(Interpreter::stringify(message)),
+        None => Err("Assertion failed.".to_owned()),
+    }
+}
+
+// The next line from `reader` without its line ending, or nil at the end of
+// the input.
+pub fn input(reader: &mut dyn BufRead) -> Result<Literal, String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => Ok(Literal::Nil),
+        Ok(_) => {
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            Ok(Literal::String(line))
+        }
+        Err(e) => Err(format!("Failed to read input: {}.", e)),
+    }
+}
+
+// A linear congruential generator (Knuth's MMIX constants). Not suitable for
+// anything security-related, but cheap and reproducible after `seed`.
+pub struct Random {
+    state: u64,
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Random::new()
+    }
+}
+
+impl Random {
+    // Seeded from the clock, so runs differ unless the script calls `seed`.
+    pub fn new() -> Self {
+        let elapsed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Random {
+            state: elapsed.as_nanos() as u64,
+        }
+    }
+
+    pub fn seed(&mut self, seed: f64) {
+        self.state = seed.to_bits();
+    }
+
+    // A number in [0, 1), built from the top 53 bits of the state since the
+    // low bits of an LCG are the least random.
+    pub fn next_f64(&mut self) -> f64 {
+        self.state = self
+            .state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.state >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// A whole number between `lo` and `hi`, inclusive.
+pub fn random_int(random: &mut Random, arguments: &[Literal]) -> Result<Literal, String> {
+    let lo = number_argument(&arguments[0], "random_int")?;
+    let hi = number_argument(&arguments[1], "random_int")?;
+    if lo.fract() != 0.0 || hi.fract() != 0.0 {
+        return Err("Bounds of 'random_int' must be whole numbers.".to_owned());
+    }
+    if lo > hi {
+        return Err("Lower bound of 'random_int' is greater than its upper bound.".to_owned());
+    }
+    let n = lo + (random.next_f64() * (hi - lo + 1.0)).floor();
+    Ok(Literal::Number(n))
+}
+
+fn number_argument(value: &Literal, function: &str) -> Result<f64, String> {
+    match value {
+        Literal::Number(n) => Ok(*n),
+        _ => Err(format!("Argument to '{}' must be a number.", function)),
+    }
+}
+
+fn set_argument<'a>(value: &'a Literal, function: &str) -> Result<&'a RefCell<LoxSet>, String> {
+    match value {
+        Literal::Set(s) => Ok(s),
+        _ => Err(format!("First argument to '{}' must be a set.", function)),
+    }
+}
+
+fn bytes_argument<'a>(value: &'a Literal, function: &str) -> Result<&'a [u8], String> {
+    match value {
+        Literal::Bytes(b) => Ok(b),
+        _ => Err(format!("Argument to '{}' must be bytes.", function)),
+    }
+}
+
+fn string_argument<'a>(value: &'a Literal, function: &str) -> Result<&'a str, String> {
+    match value {
+        Literal::String(s) => Ok(s),
+        _ => Err(format!("Argument to '{}' must be a string.", function)),
+    }
+}
+
+// The characters of `s` from `start` up to but not including `end`, counted
+// in characters rather than bytes.
+pub fn substring(arguments: &[Literal]) -> Result<Literal, String> {
+    let chars: Vec<char> = match &arguments[0] {
+        Literal::String(s) => s.chars().collect(),
+        _ => return Err("First argument to 'substring' must be a string.".to_owned()),
+    };
+    // `end` may point just past the last character.
+    let start = index(&arguments[1], chars.len() + 1)?;
+    let end = index(&arguments[2], chars.len() + 1)?;
+    if start > end {
+        return Err("Substring start is after its end.".to_owned());
+    }
+    Ok(Literal::String(chars[start..end].iter().collect()))
+}
+
+// Checks that `value` is a whole number usable as an index into a sequence
+// of `len` elements.
+pub(crate) fn index(value: &Literal, len: usize) -> Result<usize, String> {
+    match value {
+        Literal::Number(n) if n.fract() == 0.0 => {
+            if *n >= 0.0 && *n < len as f64 {
+                Ok(*n as usize)
+            } else {
+                Err(format!("Index {} is out of range.", n))
+            }
+        }
+        _ => Err("Index must be a whole number.".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn list(elements: Vec<Literal>) -> Literal {
+        Literal::List(Rc::new(RefCell::new(elements)))
+    }
+
+    fn string(s: &str) -> Literal {
+        Literal::String(s.to_owned())
+    }
+
+    fn numbers(value: Result<Literal, String>) -> Vec<f64> {
+        match value {
+            Ok(Literal::List(l)) => l
+                .borrow()
+                .iter()
+                .map(|element| match element {
+                    Literal::Number(n) => *n,
+                    _ => panic!("expected a number, got {:?}", element),
+                })
+                .collect(),
+            value => panic!("expected a list, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn range_takes_one_to_three_arguments() {
+        let n = Literal::Number;
+        assert_eq!(numbers(range(&[n(3.0)])), vec![0.0, 1.0, 2.0]);
+        assert_eq!(numbers(range(&[n(2.0), n(5.0)])), vec![2.0, 3.0, 4.0]);
+        assert_eq!(
+            numbers(range(&[n(0.0), n(1.0), n(0.25)])),
+            vec![0.0, 0.25, 0.5, 0.75]
+        );
+        assert_eq!(numbers(range(&[n(5.0), n(2.0)])), Vec::<f64>::new());
+    }
+
+    #[test]
+    fn range_counts_down_with_a_negative_step() {
+        let n = Literal::Number;
+        assert_eq!(
+            numbers(range(&[n(5.0), n(0.0), n(-2.0)])),
+            vec![5.0, 3.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn range_rejects_a_zero_step() {
+        let n = Literal::Number;
+        assert_eq!(
+            range(&[n(0.0), n(5.0), n(0.0)]),
+            Err("Step of 'range' can't be zero.".to_owned())
+        );
+    }
+
+    #[test]
+    fn range_rejects_huge_ranges() {
+        let n = Literal::Number;
+        let too_long = Err("Range is too long.".to_owned());
+        assert_eq!(range(&[n(0.0), n(1e15)]), too_long);
+        assert_eq!(range(&[n(-1e308), n(1e308)]), too_long);
+    }
+
+    #[test]
+    fn range_rejects_non_finite_arguments() {
+        let n = Literal::Number;
+        let message = Err("Arguments to 'range' must be finite.".to_owned());
+        assert_eq!(range(&[n(f64::INFINITY)]), message);
+        assert_eq!(range(&[n(0.0), n(1.0), n(f64::NAN)]), message);
+    }
+
+    #[test]
+    fn range_terminates_when_step_is_lost_to_rounding() {
+        // 2^53 + 1 isn't representable, so repeatedly adding 1 would never
+        // get past the start.
+        let n = Literal::Number;
+        let list = numbers(range(&[n(9007199254740992.0), n(9007199254740994.0)]));
+        assert_eq!(list.len(), 2);
+    }
+
+    #[test]
+    fn reductions_take_a_list() {
+        let l = list(vec![
+            Literal::Number(3.0),
+            Literal::Number(-1.0),
+            Literal::Number(2.5),
+        ]);
+        assert_eq!(min(std::slice::from_ref(&l)), Ok(Literal::Number(-1.0)));
+        assert_eq!(max(std::slice::from_ref(&l)), Ok(Literal::Number(3.0)));
+        assert_eq!(sum(&[l]), Ok(Literal::Number(4.5)));
+    }
+
+    #[test]
+    fn reductions_take_varargs() {
+        let n = Literal::Number;
+        assert_eq!(min(&[n(4.0), n(2.0), n(8.0)]), Ok(n(2.0)));
+        assert_eq!(max(&[n(4.0), n(2.0), n(8.0)]), Ok(n(8.0)));
+        assert_eq!(sum(&[n(4.0), n(2.0), n(8.0)]), Ok(n(14.0)));
+        assert_eq!(max(&[n(7.0)]), Ok(n(7.0)));
+    }
+
+    #[test]
+    fn reductions_of_an_empty_list() {
+        assert_eq!(
+            min(&[list(vec![])]),
+            Err("Can't take 'min' of an empty list.".to_owned())
+        );
+        assert_eq!(
+            max(&[list(vec![])]),
+            Err("Can't take 'max' of an empty list.".to_owned())
+        );
+        assert_eq!(sum(&[list(vec![])]), Ok(Literal::Number(0.0)));
+    }
+
+    #[test]
+    fn reductions_reject_non_numbers() {
+        let l = list(vec![Literal::Number(1.0), string("2")]);
+        assert_eq!(
+            sum(&[l]),
+            Err("Argument to 'sum' must be a number.".to_owned())
+        );
+        assert!(min(&[Literal::Number(1.0), Literal::Nil]).is_err());
+    }
+
+    #[test]
+    fn contains_finds_list_elements() {
+        let l = list(vec![Literal::Number(1.0), string("a"), Literal::Nil]);
+        assert_eq!(contains(&[l.clone(), string("a")]), Ok(Literal::Bool(true)));
+        assert_eq!(
+            contains(&[l.clone(), Literal::Nil]),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(contains(&[l, string("1")]), Ok(Literal::Bool(false)));
+    }
+
+    #[test]
+    fn contains_searches_strings() {
+        assert_eq!(
+            contains(&[string("haystack"), string("st")]),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(
+            contains(&[string("haystack"), string("needle")]),
+            Ok(Literal::Bool(false))
+        );
+        assert!(contains(&[string("haystack"), Literal::Number(1.0)]).is_err());
+    }
+
+    #[test]
+    fn contains_rejects_other_collections() {
+        assert_eq!(
+            contains(&[Literal::Number(1.0), Literal::Number(1.0)]),
+            Err("First argument to 'contains' must be a list or a string.".to_owned())
+        );
+    }
+
+    fn encode(s: &str) -> Result<Literal, String> {
+        base64_encode(&[bytes(&[string(s)]).unwrap()])
+    }
+
+    fn decode(s: &str) -> Result<Literal, String> {
+        from_bytes(&[base64_decode(&[string(s)])?])
+    }
+
+    #[test]
+    fn base64_round_trips() {
+        let cases = vec![
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("héllo", "aMOpbGxv"),
+        ];
+        for (text, encoded) in cases {
+            assert_eq!(encode(text), Ok(string(encoded)));
+            assert_eq!(decode(encoded), Ok(string(text)));
+        }
+        let all: Vec<u8> = (0..=255).collect();
+        let bytes = Literal::Bytes(Rc::new(all));
+        let encoded = base64_encode(std::slice::from_ref(&bytes)).unwrap();
+        assert_eq!(base64_decode(&[encoded]), Ok(bytes));
+    }
+
+    #[test]
+    fn base64_decode_rejects_invalid_input() {
+        for input in &["Zg=", "Zm9v!A==", "Z===", "Zg==Zm9v", "Zm 9v"] {
+            assert_eq!(
+                base64_decode(&[string(input)]),
+                Err("Invalid base64 input.".to_owned()),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            base64_decode(&[Literal::Number(1.0)]),
+            Err("Argument to 'base64Decode' must be a string.".to_owned())
+        );
+    }
+
+    #[test]
+    fn from_bytes_rejects_invalid_utf8() {
+        let invalid = Literal::Bytes(Rc::new(vec![0xff]));
+        assert_eq!(
+            from_bytes(&[invalid]),
+            Err("Bytes passed to 'fromBytes' are not valid UTF-8.".to_owned())
+        );
+    }
+
+    #[test]
+    fn clock_counts_seconds_since_the_epoch() {
+        let seconds = |value| match value {
+            Ok(Literal::Number(n)) => n,
+            value => panic!("expected a number, got {:?}", value),
+        };
+        let before = seconds(clock(&[]));
+        let after = seconds(clock(&[]));
+        // Some time after 2020-01-01, and not going backwards.
+        assert!(before > 1_577_836_800.0);
+        assert!(after >= before);
+    }
+
+    #[test]
+    fn len_counts_characters_not_bytes() {
+        assert_eq!(len(&[string("")]), Ok(Literal::Number(0.0)));
+        assert_eq!(len(&[string("héllo")]), Ok(Literal::Number(5.0)));
+        assert_eq!(
+            len(&[list(vec![Literal::Nil, Literal::Nil])]),
+            Ok(Literal::Number(2.0))
+        );
+        assert_eq!(
+            len(&[Literal::Number(3.0)]),
+            Err("Argument to 'len' must be a string, a list or bytes.".to_owned())
+        );
+    }
+
+    #[test]
+    fn substring_takes_a_character_range() {
+        let n = Literal::Number;
+        assert_eq!(
+            substring(&[string("héllo"), n(1.0), n(3.0)]),
+            Ok(string("él"))
+        );
+        assert_eq!(substring(&[string("abc"), n(3.0), n(3.0)]), Ok(string("")));
+        assert_eq!(
+            substring(&[string("abc"), n(2.0), n(1.0)]),
+            Err("Substring start is after its end.".to_owned())
+        );
+        assert_eq!(
+            substring(&[string("abc"), n(0.0), n(4.0)]),
+            Err("Index 4 is out of range.".to_owned())
+        );
+        assert_eq!(
+            substring(&[string("abc"), n(0.5), n(1.0)]),
+            Err("Index must be a whole number.".to_owned())
+        );
+    }
+
+    #[test]
+    fn case_and_trim_natives_take_strings() {
+        assert_eq!(upper(&[string("Straße")]), Ok(string("STRASSE")));
+        assert_eq!(lower(&[string("ÀBC")]), Ok(string("àbc")));
+        assert_eq!(trim(&[string("\t a b \n")]), Ok(string("a b")));
+        assert_eq!(
+            trim(&[Literal::Nil]),
+            Err("Argument to 'trim' must be a string.".to_owned())
+        );
+    }
+
+    fn strings(value: Result<Literal, String>) -> Vec<String> {
+        match value {
+            Ok(Literal::List(l)) => l
+                .borrow()
+                .iter()
+                .map(|element| match element {
+                    Literal::String(s) => s.clone(),
+                    _ => panic!("expected a string, got {:?}", element),
+                })
+                .collect(),
+            value => panic!("expected a list, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn split_keeps_empty_pieces() {
+        assert_eq!(
+            strings(split(&[string("a,,b,"), string(",")])),
+            ["a", "", "b", ""]
+        );
+        assert_eq!(strings(split(&[string("a--b"), string("--")])), ["a", "b"]);
+        assert_eq!(strings(split(&[string(""), string(",")])), [""]);
+        assert_eq!(strings(split(&[string("hé"), string("")])), ["h", "é"]);
+        assert_eq!(
+            split(&[string("a"), Literal::Nil]),
+            Err("Argument to 'split' must be a string.".to_owned())
+        );
+    }
+
+    #[test]
+    fn math_natives() {
+        let n = Literal::Number;
+        assert_eq!(sqrt(&[n(16.0)]), Ok(n(4.0)));
+        assert_eq!(
+            sqrt(&[n(-1.0)]),
+            Err("Can't take the square root of a negative number.".to_owned())
+        );
+        assert_eq!(abs(&[n(-2.5)]), Ok(n(2.5)));
+        assert_eq!(floor(&[n(-2.5)]), Ok(n(-3.0)));
+        assert_eq!(ceil(&[n(-2.5)]), Ok(n(-2.0)));
+        assert_eq!(pow(&[n(2.0), n(10.0)]), Ok(n(1024.0)));
+        assert_eq!(pow(&[n(4.0), n(0.5)]), Ok(n(2.0)));
+        assert_eq!(
+            pow(&[n(2.0), string("3")]),
+            Err("Argument to 'pow' must be a number.".to_owned())
+        );
+    }
+
+    #[test]
+    fn random_numbers_repeat_after_reseeding() {
+        let mut random = Random::new();
+        random.seed(42.0);
+        let first: Vec<f64> = (0..5).map(|_| random.next_f64()).collect();
+        assert!(first.iter().all(|n| (0.0..1.0).contains(n)));
+        random.seed(42.0);
+        let again: Vec<f64> = (0..5).map(|_| random.next_f64()).collect();
+        assert_eq!(first, again);
+    }
+
+    #[test]
+    fn random_int_stays_within_its_bounds() {
+        let n = Literal::Number;
+        let mut random = Random::new();
+        for _ in 0..1000 {
+            match random_int(&mut random, &[n(-2.0), n(2.0)]) {
+                Ok(Literal::Number(x)) => assert!((-2.0..=2.0).contains(&x) && x.fract() == 0.0),
+                value => panic!("expected a number, got {:?}", value),
+            }
+        }
+        assert_eq!(random_int(&mut random, &[n(3.0), n(3.0)]), Ok(n(3.0)));
+        assert_eq!(
+            random_int(&mut random, &[n(0.5), n(3.0)]),
+            Err("Bounds of 'random_int' must be whole numbers.".to_owned())
+        );
+        assert_eq!(
+            random_int(&mut random, &[n(3.0), n(2.0)]),
+            Err("Lower bound of 'random_int' is greater than its upper bound.".to_owned())
+        );
+    }
+
+    #[test]
+    fn input_reads_one_line_at_a_time() {
+        let mut reader = io::Cursor::new("first\r\n\nlast");
+        assert_eq!(input(&mut reader), Ok(string("first")));
+        assert_eq!(input(&mut reader), Ok(string("")));
+        assert_eq!(input(&mut reader), Ok(string("last")));
+        assert_eq!(input(&mut reader), Ok(Literal::Nil));
+    }
+
+    #[test]
+    fn num_and_str_convert_between_numbers_and_strings() {
+        let n = Literal::Number;
+        assert_eq!(num(&[string(" 2.5\n")]), Ok(n(2.5)));
+        assert_eq!(num(&[string("-1e3")]), Ok(n(-1000.0)));
+        assert_eq!(
+            num(&[string("12abc")]),
+            Err("Can't convert '12abc' to a number.".to_owned())
+        );
+        assert_eq!(
+            num(&[n(1.0)]),
+            Err("Argument to 'num' must be a string.".to_owned())
+        );
+        assert_eq!(str(&[n(3.0)]), Ok(string("3")));
+        assert_eq!(str(&[n(0.5)]), Ok(string("0.5")));
+        assert_eq!(str(&[Literal::Nil]), Ok(string("nil")));
+        assert_eq!(str(&[Literal::Bool(true)]), Ok(string("true")));
+    }
+
+    #[test]
+    fn type_of_names_each_kind_of_value() {
+        let cases = vec![
+            (Literal::Number(1.0), "number"),
+            (string("a"), "string"),
+            (Literal::Bool(false), "bool"),
+            (Literal::Nil, "nil"),
+            (list(Vec::new()), "list"),
+            (Literal::Bytes(Rc::new(Vec::new())), "bytes"),
+        ];
+        for (value, name) in cases {
+            assert_eq!(type_of(&[value]), Ok(string(name)));
+        }
+    }
+
+    #[test]
+    fn assert_fails_on_falsey_conditions() {
+        assert_eq!(assert(&[Literal::Number(0.0)]), Ok(Literal::Nil));
+        assert_eq!(assert(&[Literal::Nil]), Err("Assertion failed.".to_owned()));
+        assert_eq!(
+            assert(&[Literal::Bool(false), string("x must be positive")]),
+            Err("x must be positive".to_owned())
+        );
+        assert_eq!(
+            assert(&[Literal::Bool(false), Literal::Number(42.0)]),
+            Err("42".to_owned())
+        );
+    }
+
+    #[test]
+    fn sets_reject_nan() {
+        let nan = Literal::Number(f64::NAN);
+        let message = Err("Sets can't contain NaN.".to_owned());
+        assert_eq!(
+            set(&[list(vec![Literal::Number(1.0), nan.clone()])]),
+            message
+        );
+        let s = set(&[]).unwrap();
+        assert_eq!(add(&[s.clone(), nan.clone()]), message);
+        assert_eq!(has(&[s, nan]), Ok(Literal::Bool(false)));
+    }
+}
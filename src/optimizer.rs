@@ -0,0 +1,276 @@
+use crate::ast::{Expr, Stmt};
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::token::Literal;
+use std::io;
+
+// Replaces every operator expression whose operands are all literals with the
+// literal it evaluates to, innermost first, so `(1 + 2) * x` becomes `3 * x`.
+// A grouping is unwrapped only once its contents fold; around anything else
+// it stays, keeping the tree's shape for later passes. An expression that
+// fails at runtime is left alone, so the error is still reported when (and
+// if) it runs.
+pub fn fold_constants(statements: &mut [Box<Stmt>]) {
+    let mut scratch = scratch_interpreter();
+    for statement in statements {
+        fold_statement(&mut scratch, statement);
+    }
+}
+
+fn fold_statement(scratch: &mut Interpreter, statement: &mut Stmt) {
+    match statement {
+        Stmt::Block(statements)
+        | Stmt::Function(_, _, statements)
+        | Stmt::Class(_, _, statements) => {
+            for statement in statements {
+                fold_statement(scratch, statement);
+            }
+        }
+        Stmt::Expr(expr) | Stmt::Print(expr) => fold(scratch, expr),
+        Stmt::Var(_, initializer) => {
+            if let Some(initializer) = initializer.as_mut() {
+                fold(scratch, initializer);
+            }
+        }
+        Stmt::If(_, condition, then_branch, else_branch) => {
+            fold(scratch, condition);
+            fold_statement(scratch, then_branch);
+            if let Some(else_branch) = else_branch {
+                fold_statement(scratch, else_branch);
+            }
+        }
+        Stmt::While(_, condition, body, increment) => {
+            fold(scratch, condition);
+            fold_statement(scratch, body);
+            if let Some(increment) = increment {
+                fold(scratch, increment);
+            }
+        }
+        Stmt::ForIn(_, _, iterable, body) => {
+            fold(scratch, iterable);
+            fold_statement(scratch, body);
+        }
+        Stmt::Return(_, Some(value)) => fold(scratch, value),
+        Stmt::Return(_, None) | Stmt::Break(_) | Stmt::Continue(_) => {}
+    }
+}
+
+fn fold(scratch: &mut Interpreter, expr: &mut Expr) {
+    let foldable = match expr {
+        Expr::Literal(_) | Expr::Variable(_, _) | Expr::This(_, _) | Expr::Super(_, _, _) => false,
+        Expr::Assignment(_, value, _) => {
+            fold(scratch, value);
+            false
+        }
+        Expr::Get(object, _) => {
+            fold(scratch, object);
+            false
+        }
+        Expr::Index(object, _, index) | Expr::Set(object, _, index) => {
+            fold(scratch, object);
+            fold(scratch, index);
+            false
+        }
+        Expr::Call(callee, _, arguments) => {
+            fold(scratch, callee);
+            for argument in arguments.iter_mut() {
+                fold(scratch, argument);
+            }
+            false
+        }
+        Expr::Grouping(e) | Expr::Unary(_, e) => {
+            fold(scratch, e);
+            is_literal(e)
+        }
+        Expr::Binary(lhs, _, rhs) | Expr::Logical(lhs, _, rhs) => {
+            fold(scratch, lhs);
+            fold(scratch, rhs);
+            is_literal(lhs) && is_literal(rhs)
+        }
+        Expr::Ternary(condition, then_branch, else_branch) => {
+            fold(scratch, condition);
+            fold(scratch, then_branch);
+            fold(scratch, else_branch);
+            is_literal(condition) && is_literal(then_branch) && is_literal(else_branch)
+        }
+    };
+    if foldable {
+        if let Ok(value) = evaluate(scratch, expr) {
+            *expr = Expr::Literal(value);
+        }
+    }
+}
+
+fn is_literal(expr: &Expr) -> bool {
+    matches!(expr, Expr::Literal(_))
+}
+
+// Drops expression statements that can't have any effect: ones built only
+// from literals and operators, whose value is thrown away. Anything that
+// reads a variable, calls, touches a property or assigns is kept, and so is a
+// pure expression that fails at runtime (e.g. `1 / 0;`), so its error is
+// still reported. The REPL doesn't run this, since it echoes the values of
+// expression statements.
+pub fn strip_pure_expressions(statements: &mut Vec<Box<Stmt>>) {
+    let mut scratch = scratch_interpreter();
+    statements.retain(|statement| !is_discardable(&mut scratch, statement));
+    for statement in statements.iter_mut() {
+        strip_nested(&mut scratch, statement);
+    }
+}
+
+fn strip_block(scratch: &mut Interpreter, statements: &mut Vec<Stmt>) {
+    statements.retain(|statement| !is_discardable(scratch, statement));
+    for statement in statements.iter_mut() {
+        strip_nested(scratch, statement);
+    }
+}
+
+// A statement that is itself a branch or loop body is left in place even if
+// it's discardable, since removing it would leave a hole in the syntax tree.
+fn strip_nested(scratch: &mut Interpreter, statement: &mut Stmt) {
+    match statement {
+        Stmt::Block(statements) | Stmt::Function(_, _, statements) => {
+            strip_block(scratch, statements)
+        }
+        Stmt::If(_, _, then_branch, else_branch) => {
+            strip_nested(scratch, then_branch);
+            if let Some(else_branch) = else_branch {
+                strip_nested(scratch, else_branch);
+            }
+        }
+        Stmt::While(_, _, body, _) | Stmt::ForIn(_, _, _, body) => strip_nested(scratch, body),
+        Stmt::Class(_, _, methods) => {
+            for method in methods {
+                strip_nested(scratch, method);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_discardable(scratch: &mut Interpreter, statement: &Stmt) -> bool {
+    match statement {
+        Stmt::Expr(expr) => is_pure(expr) && evaluates_cleanly(scratch, expr),
+        _ => false,
+    }
+}
+
+fn is_pure(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_) => true,
+        Expr::Grouping(e) | Expr::Unary(_, e) => is_pure(e),
+        Expr::Binary(lhs, _, rhs) | Expr::Logical(lhs, _, rhs) => is_pure(lhs) && is_pure(rhs),
+        Expr::Ternary(condition, then_branch, else_branch) => {
+            is_pure(condition) && is_pure(then_branch) && is_pure(else_branch)
+        }
+        _ => false,
+    }
+}
+
+// A pure expression always evaluates the same way, so trying it once now
+// tells whether it would raise an error when the script runs.
+fn evaluates_cleanly(scratch: &mut Interpreter, expr: &Expr) -> bool {
+    evaluate(scratch, expr).is_ok()
+}
+
+// An interpreter with the default modes, made once per pass and used for
+// every expression the pass evaluates.
+fn scratch_interpreter() -> Interpreter {
+    Interpreter::with_output(Box::new(io::sink()))
+}
+
+// Evaluates an expression that doesn't refer to any variables, so the
+// scratch interpreter's state never affects the result. The modes that make
+// an interpreter more permissive only turn errors into values, so a value
+// found with the defaults is the one any interpreter would get.
+fn evaluate(scratch: &mut Interpreter, expr: &Expr) -> Result<Literal, RuntimeError> {
+    scratch.evaluate(expr.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Lox;
+
+    #[test]
+    fn drops_a_pure_expression_statement() {
+        let statements = Lox::compile("1 + 2; print 3;", false).unwrap();
+        assert_eq!(statements.len(), 1);
+        assert!(matches!(*statements[0], Stmt::Print(_)));
+    }
+
+    #[test]
+    fn keeps_calls_and_assignments() {
+        let statements = Lox::compile("var a = 1; a = 2; clock(); a;", false).unwrap();
+        assert_eq!(statements.len(), 4);
+    }
+
+    #[test]
+    fn keeps_a_pure_expression_that_fails() {
+        assert_eq!(Lox::compile("1 / 0;", false).unwrap().len(), 1);
+        assert_eq!(Lox::compile("-\"a\";", false).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn strips_inside_blocks_and_function_bodies() {
+        let statements =
+            Lox::compile("fun f() { (1 + 2) * 3; return true and nil; }", false).unwrap();
+        match &*statements[0] {
+            Stmt::Function(_, _, body) => {
+                assert_eq!(body.len(), 1);
+                assert!(matches!(body[0], Stmt::Return(_, _)));
+            }
+            statement => panic!("expected a function, got {:?}", statement),
+        }
+    }
+
+    fn folded(source: &str) -> Expr {
+        let mut statements = Lox::compile(&format!("print {};", source), false).unwrap();
+        match *statements.remove(0) {
+            Stmt::Print(expr) => *expr,
+            statement => panic!("expected a print, got {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn folds_a_grouping_whose_contents_fold() {
+        assert!(matches!(folded("(1 + 2)"), Expr::Literal(Literal::Number(n)) if n == 3.0));
+        assert!(matches!(
+            folded("(\"a\" + \"b\") + \"c\""),
+            Expr::Literal(Literal::String(s)) if s == "abc"
+        ));
+    }
+
+    #[test]
+    fn preserves_a_grouping_around_a_variable() {
+        let expr = folded("(clock + (2 * 3)) * 4");
+        let grouping = match expr {
+            Expr::Binary(lhs, _, rhs) => {
+                assert!(matches!(*rhs, Expr::Literal(Literal::Number(n)) if n == 4.0));
+                *lhs
+            }
+            expr => panic!("expected a binary, got {:?}", expr),
+        };
+        match grouping {
+            Expr::Grouping(inner) => match *inner {
+                Expr::Binary(lhs, _, rhs) => {
+                    assert!(matches!(*lhs, Expr::Variable(_, _)));
+                    assert!(matches!(*rhs, Expr::Literal(Literal::Number(n)) if n == 6.0));
+                }
+                expr => panic!("expected a binary, got {:?}", expr),
+            },
+            expr => panic!("expected a grouping, got {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn leaves_failing_expressions_unfolded() {
+        assert!(matches!(folded("(1 + nil)"), Expr::Grouping(_)));
+    }
+
+    #[test]
+    fn repl_keeps_expressions_for_echoing() {
+        let statements = Lox::compile("1 + 2", true).unwrap();
+        assert_eq!(statements.len(), 1);
+    }
+}
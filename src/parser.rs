@@ -1,6 +1,6 @@
 use crate::ast::*;
 use crate::token::{Literal, Token, TokenType};
-use crate::Lox;
+use std::mem;
 
 // ------------ Syntax Grammar ------------
 //
@@ -9,78 +9,216 @@ use crate::Lox;
 // program          -> declaration* EOF
 //
 // -------- Declarations --------
-// declaration      -> varDeclaration
+// declaration      -> classDeclaration
+//                   | funDeclaration
+//                   | varDeclaration
 //                   | statement ;
+// classDeclaration -> "class" IDENTIFIER ( "<" IDENTIFIER )? "{" function* "}" ;
+// funDeclaration   -> "fun" function ;
+// function         -> IDENTIFIER "(" parameters? ")" block ;
+// parameters       -> IDENTIFIER ( "," IDENTIFIER )* ;
 // -------- Statements --------
 // statement        -> exprStmt
 //                   | ifStmt
 //                   | whileStmt
+//                   | forStmt
 //                   | printStmt
+//                   | returnStmt
+//                   | breakStmt
+//                   | continueStmt
 //                   | block ;
+// ifStmt           -> "if" "(" expression ")" statement
+//                     ( "else" statement )? ;
+//                     (a dangling "else" binds to the nearest "if")
+// forStmt          -> "for" "(" ( varDecl | exprStmt | ";" )
+//                     expression? ";" expression? ")" statement
+//                   | "for" "(" IDENTIFIER "in" expression ")" statement ;
 // block            -> "{" declaration* "}" ;
 // exprStmt         -> expression ";" ;
 // printStmt        -> "print" expression ";" ;
+// returnStmt       -> "return" expression? ";" ;
+// breakStmt        -> "break" ";" ;      (only inside a loop body)
+// continueStmt     -> "continue" ";" ;   (only inside a loop body)
 // -------- EXPRESSIONS --------
 // expression       -> assignment ;
-// assignment       -> IDENTIFIER "=" assignment
-//                   | logic_or ;
+// assignment       -> ( call "." )? IDENTIFIER ( "=" | "+=" | "-=" | "*=" | "/=" ) assignment
+//                   | ternary ;
+// ternary          -> logic_or ( "?" expression ":" ternary )? ;
 // logic_or         -> logic_and ("or" logic_and)* ;
-// logic_and        -> equality ("or" equality)* ;
+// logic_and        -> equality ("and" equality)* ;
 // equality         -> comparison ( ("!=" | "==" ) comparison )* ;
 // comparison       -> term ( (">" | ">=" | "<=" | "<" ) term )* ;
-// unary            -> ( "-" | "!" ) unary | primary ;
+// unary            -> ( "-" | "!" ) unary | call ;
+// call             -> primary ( "(" arguments? ")" | "." IDENTIFIER | "[" expression "]" )* ;
+// arguments        -> expression ( "," expression )* ;
 // term             -> factor ( ("-" | "+") factor)* ;
-// factor           -> unary ( ("/" | "*") unary)* ;
-// primary          ->  NUMBER | String | "true" | "false" | "nil" | "(" expression ")" | IDENTIFIER ;
+// factor           -> unary ( ("/" | "*" | "%") unary)* ;
+//                     (equality, comparison, term and factor report a leading
+//                     binary operator as missing its left-hand operand)
+// primary          ->  NUMBER | String | "true" | "false" | "nil" | "this" | "(" expression ")" | IDENTIFIER
+//                   | "super" "." IDENTIFIER ;
 
 pub struct Parser {
     current: usize,
     tokens: Vec<Token>,
+    errors: Vec<ParseError>,
+    // How many function bodies enclose the current token.
+    function_depth: usize,
+    // How many loop bodies enclose the current token within the innermost
+    // function.
+    loop_depth: usize,
+    // One entry per enclosing class body, recording whether that class has a
+    // superclass.
+    classes: Vec<bool>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { current: 0, tokens }
+        Parser {
+            current: 0,
+            tokens,
+            errors: Vec::new(),
+            function_depth: 0,
+            loop_depth: 0,
+            classes: Vec::new(),
+        }
     }
 
-    pub fn parse(&mut self) -> Vec<Box<Stmt>> {
+    // REPL input may be a lone expression without a trailing ';', which is
+    // treated as an expression statement so its value gets echoed. Anything
+    // else is parsed as a regular program.
+    pub fn parse_repl(&mut self) -> (Vec<Box<Stmt>>, Vec<ParseError>) {
+        if let Ok(expr) = self.expression() {
+            if self.at_end() {
+                return (
+                    vec![Box::new(Stmt::Expr(Box::new(expr)))],
+                    self.errors.drain(..).collect(),
+                );
+            }
+        }
+        self.current = 0;
+        self.errors.clear();
+        self.parse()
+    }
+
+    // Parses the whole program, recovering after each syntax error so every
+    // error in the file is found in one pass. Statements that parsed are
+    // returned alongside the errors; after a syntax error they're only good
+    // for tooling, not for running.
+    pub fn parse(&mut self) -> (Vec<Box<Stmt>>, Vec<ParseError>) {
         let mut statements: Vec<Box<Stmt>> = Vec::new();
         while !self.at_end() {
-            match self.declaration() {
-                Some(s) => statements.push(Box::new(s)),
-                None => {}
+            if let Some(s) = self.declaration() {
+                statements.push(Box::new(s));
             }
         }
 
-        return statements;
+        (statements, self.errors.drain(..).collect())
     }
+
     fn declaration(&mut self) -> Option<Stmt> {
-        if self.match_(&vec![TokenType::Var]) {
-            match self.var_declaration() {
-                Ok(s) => Some(s),
-                Err(e) => {
-                    self.syncronize();
-                    Lox::parse_error(e);
-                    None
-                }
-            }
+        let statement = if self.match_(&[TokenType::Class]) {
+            self.class_declaration()
+        } else if self.match_(&[TokenType::Fun]) {
+            self.function("function")
+        } else if self.match_(&[TokenType::Var]) {
+            self.var_declaration()
         } else {
-            match self.statement() {
-                Ok(s) => Some(s),
-                Err(e) => {
-                    self.syncronize();
-                    Lox::parse_error(e);
-                    None
+            self.statement()
+        };
+        match statement {
+            Ok(s) => Some(s),
+            Err(e) => {
+                self.syncronize();
+                self.errors.push(e);
+                None
+            }
+        }
+    }
+
+    fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let name = self.consume(TokenType::Identifier, &"Expect class name.".to_owned())?;
+        let mut superclass = None;
+        if self.match_(&[TokenType::Less]) {
+            let superclass_name =
+                self.consume(TokenType::Identifier, &"Expect superclass name.".to_owned())?;
+            if superclass_name.lexeme == name.lexeme {
+                self.errors.push(ParseError::new(
+                    superclass_name.clone(),
+                    "A class can't inherit from itself.".to_owned(),
+                ));
+            }
+            superclass = Some(Expr::Variable(superclass_name, None));
+        }
+        self.consume(
+            TokenType::LeftBrace,
+            &"Expect '{' before class body.".to_owned(),
+        )?;
+        self.classes.push(superclass.is_some());
+        let methods = self.methods();
+        self.classes.pop();
+        let methods = methods?;
+        self.consume(
+            TokenType::RightBrace,
+            &"Expect '}' after class body.".to_owned(),
+        )?;
+        Ok(Stmt::Class(name, superclass, methods))
+    }
+
+    fn methods(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        let mut methods: Vec<Stmt> = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.at_end() {
+            methods.push(self.function("method")?);
+        }
+        Ok(methods)
+    }
+
+    fn function(&mut self, kind: &str) -> Result<Stmt, ParseError> {
+        let name = self.consume(TokenType::Identifier, &format!("Expect {} name.", kind))?;
+        self.consume(
+            TokenType::LeftParen,
+            &format!("Expect '(' after {} name.", kind),
+        )?;
+        let mut parameters: Vec<Token> = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if parameters.len() >= 255 {
+                    // Reported without unwinding: the parser isn't confused.
+                    self.errors.push(ParseError::new(
+                        self.peek().clone(),
+                        "Can't have more than 255 parameters.".to_owned(),
+                    ));
+                }
+                parameters.push(
+                    self.consume(TokenType::Identifier, &"Expect parameter name.".to_owned())?,
+                );
+                if !self.match_(&[TokenType::Comma]) {
+                    break;
                 }
             }
         }
+        self.consume(
+            TokenType::RightParen,
+            &"Expect ')' after parameters.".to_owned(),
+        )?;
+        self.consume(
+            TokenType::LeftBrace,
+            &format!("Expect '{{' before {} body.", kind),
+        )?;
+        // A loop around the declaration doesn't make `break` valid in the body.
+        let loop_depth = mem::replace(&mut self.loop_depth, 0);
+        self.function_depth += 1;
+        let body = self.block();
+        self.function_depth -= 1;
+        self.loop_depth = loop_depth;
+        Ok(Stmt::Function(name, parameters, body?))
     }
 
     fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
         let name: Token =
             self.consume(TokenType::Identifier, &"Expect variable name.".to_owned())?;
         let mut initializer = Expr::Literal(Literal::Nil);
-        if self.match_(&vec![TokenType::Equal]) {
+        if self.match_(&[TokenType::Equal]) {
             initializer = self.expression()?;
         }
         self.consume(
@@ -91,15 +229,19 @@ impl Parser {
     }
 
     fn statement(&mut self) -> Result<Stmt, ParseError> {
-        if self.match_(&vec![TokenType::If]) {
+        if self.match_(&[TokenType::If]) {
             self.if_statement()
-        } else if self.match_(&vec![TokenType::For]) {
+        } else if self.match_(&[TokenType::For]) {
             self.for_statement()
-        } else if self.match_(&vec![TokenType::Print]) {
+        } else if self.match_(&[TokenType::Print]) {
             self.print_statement()
-        } else if self.match_(&vec![TokenType::While]) {
+        } else if self.match_(&[TokenType::Return]) {
+            self.return_statement()
+        } else if self.match_(&[TokenType::Break, TokenType::Continue]) {
+            self.loop_control_statement()
+        } else if self.match_(&[TokenType::While]) {
             self.while_statement()
-        } else if self.match_(&vec![TokenType::LeftBrace]) {
+        } else if self.match_(&[TokenType::LeftBrace]) {
             self.block_statement()
         } else {
             self.expression_statement()
@@ -107,92 +249,185 @@ impl Parser {
     }
 
     fn while_statement(&mut self) -> Result<Stmt, ParseError> {
-        self.consume(TokenType::LeftParen, &"Expect '(' after 'while'.".to_owned())?;
+        let keyword = self.previous();
+        self.consume(
+            TokenType::LeftParen,
+            &"Expect '(' after 'while'.".to_owned(),
+        )?;
         let condition = self.expression()?;
         self.consume(
             TokenType::RightParen,
             &"Expect ')' after 'condition'".to_owned(),
         )?;
-        let body = self.statement()?;
-        Ok(Stmt::While(Box::new(condition), Box::new(body)))
+        let body = self.loop_body()?;
+        Ok(Stmt::While(
+            keyword,
+            Box::new(condition),
+            Box::new(body),
+            None,
+        ))
     }
 
     fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous();
         self.consume(TokenType::LeftParen, &"Expect '(' after 'for'.".to_owned())?;
+        if self.check(&TokenType::Identifier) && self.check_next(&TokenType::In) {
+            return self.for_in_statement(keyword);
+        }
         let initializer: Option<Stmt>;
 
-        if self.match_(&vec![TokenType::Semicolon]) {
+        if self.match_(&[TokenType::Semicolon]) {
             initializer = None;
-        } else if self.match_(&vec![TokenType::Var]) {
+        } else if self.match_(&[TokenType::Var]) {
             initializer = Some(self.var_declaration()?);
         } else {
-            initializer = Some(self.expression_statement()?); 
+            initializer = Some(self.expression_statement()?);
         }
 
         let mut condition = None;
-        if !self.check(TokenType::Semicolon) {
+        if !self.check(&TokenType::Semicolon) {
             condition = Some(self.expression()?);
         }
 
-        self.consume(TokenType::Semicolon, &"Expect ';' after loop condition.".to_owned())?;
+        self.consume(
+            TokenType::Semicolon,
+            &"Expect ';' after loop condition.".to_owned(),
+        )?;
 
         let mut increment = None;
-        if !self.check(TokenType::RightParen) {
+        if !self.check(&TokenType::RightParen) {
             increment = Some(self.expression()?);
         }
 
-        self.consume(TokenType::RightParen, &"Expect ')' after for clauses.".to_owned())?;
-        let mut body = self.statement()?;
-
-        if increment.is_some() {
-            body = Stmt::Block(vec![body, Stmt::Expr(Box::new(increment.unwrap()))])
-        }
+        self.consume(
+            TokenType::RightParen,
+            &"Expect ')' after for clauses.".to_owned(),
+        )?;
+        let mut body = self.loop_body()?;
 
         if condition.is_none() {
             condition = Some(Expr::Literal(Literal::Bool(true)));
-            }
+        }
 
-        body = Stmt::While(Box::new(condition.unwrap()), Box::new(body));
+        body = Stmt::While(
+            keyword,
+            Box::new(condition.unwrap()),
+            Box::new(body),
+            increment.map(Box::new),
+        );
 
-        if initializer.is_some() {
-            body = Stmt::Block(vec![initializer.unwrap(), body]);
-            }
+        if let Some(initializer) = initializer {
+            body = Stmt::Block(vec![initializer, body]);
+        }
         Ok(body)
     }
 
+    fn for_in_statement(&mut self, keyword: Token) -> Result<Stmt, ParseError> {
+        let name = self.advance();
+        self.advance();
+        let iterable = self.expression()?;
+        self.consume(
+            TokenType::RightParen,
+            &"Expect ')' after for clauses.".to_owned(),
+        )?;
+        let body = self.loop_body()?;
+        Ok(Stmt::ForIn(
+            keyword,
+            name,
+            Box::new(iterable),
+            Box::new(body),
+        ))
+    }
+
+    fn loop_body(&mut self) -> Result<Stmt, ParseError> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
+
+    fn loop_control_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous();
+        if self.loop_depth == 0 {
+            self.errors.push(ParseError::new(
+                keyword.clone(),
+                format!("Can't use '{}' outside of a loop.", keyword.lexeme),
+            ));
+        }
+        self.consume(
+            TokenType::Semicolon,
+            &format!("Expect ';' after '{}'.", keyword.lexeme),
+        )?;
+        match keyword.token_type {
+            TokenType::Break => Ok(Stmt::Break(keyword)),
+            _ => Ok(Stmt::Continue(keyword)),
+        }
+    }
+
     fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous();
         self.consume(TokenType::LeftParen, &"Expect '(' after 'if'.".to_owned())?;
-        let condition = self.expression().unwrap();
+        let condition = self.expression()?;
         self.consume(
             TokenType::RightParen,
             &"Expect ')' after condition.".to_owned(),
         )?;
 
+        // The then branch is parsed first, so a nested `if` claims any `else`
+        // that follows it before we get a chance to look for one.
         let then_branch = self.statement()?;
-        if self.match_(&vec![TokenType::Else]) {
+        if self.match_(&[TokenType::Else]) {
             let else_branch = self.statement()?;
             Ok(Stmt::If(
+                keyword,
                 Box::new(condition),
                 Box::new(then_branch),
                 Some(Box::new(else_branch)),
             ))
         } else {
-            Ok(Stmt::If(Box::new(condition), Box::new(then_branch), None))
+            Ok(Stmt::If(
+                keyword,
+                Box::new(condition),
+                Box::new(then_branch),
+                None,
+            ))
         }
     }
 
     fn block_statement(&mut self) -> Result<Stmt, ParseError> {
+        Ok(Stmt::Block(self.block()?))
+    }
+
+    // Parses the declarations of a block whose '{' has already been consumed.
+    fn block(&mut self) -> Result<Vec<Stmt>, ParseError> {
         let mut statements: Vec<Stmt> = Vec::new();
-        loop {
+        while !self.check(&TokenType::RightBrace) && !self.at_end() {
             if let Some(stmt) = self.declaration() {
                 statements.push(stmt);
             }
-            if self.check(TokenType::RightBrace) || self.at_end() {
-                break;
-            }
         }
         self.consume(TokenType::RightBrace, &"Expect '}' after block.".to_owned())?;
-        Ok(Stmt::Block(statements))
+        Ok(statements)
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous();
+        if self.function_depth == 0 {
+            self.errors.push(ParseError::new(
+                keyword.clone(),
+                "Can't return from top-level code.".to_owned(),
+            ));
+        }
+        let value = if self.check(&TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(
+            TokenType::Semicolon,
+            &"Expect ';' after return value.".to_owned(),
+        )?;
+        Ok(Stmt::Return(keyword, value))
     }
 
     fn print_statement(&mut self) -> Result<Stmt, ParseError> {
@@ -233,13 +468,25 @@ impl Parser {
     }
 
     fn assignment(&mut self) -> Result<Expr, ParseError> {
-        let expr = self.or()?;
+        let expr = self.ternary()?;
+
+        if self.match_(&[
+            TokenType::PlusEqual,
+            TokenType::MinusEqual,
+            TokenType::StarEqual,
+            TokenType::SlashEqual,
+        ]) {
+            let operator = self.previous();
+            let value = self.assignment()?;
+            return Parser::compound_assignment(expr, operator, value);
+        }
 
-        if self.match_(&vec![TokenType::Equal]) {
+        if self.match_(&[TokenType::Equal]) {
             let equals = self.previous();
             let value = self.assignment()?;
             match expr {
-                Expr::Variable(t) => Ok(Expr::Assignment(t, Box::new(value))),
+                Expr::Variable(t, _) => Ok(Expr::Assignment(t, Box::new(value), None)),
+                Expr::Get(object, name) => Ok(Expr::Set(object, name, Box::new(value))),
                 _ => Err(ParseError::new(
                     equals,
                     "Invalid assignment target.".to_owned(),
@@ -250,9 +497,56 @@ impl Parser {
         }
     }
 
+    // Desugars `target op= value` into `target = target op value`. A property
+    // target's object expression ends up evaluated twice.
+    fn compound_assignment(target: Expr, operator: Token, value: Expr) -> Result<Expr, ParseError> {
+        let (token_type, lexeme) = match operator.token_type {
+            TokenType::PlusEqual => (TokenType::Plus, "+"),
+            TokenType::MinusEqual => (TokenType::Minus, "-"),
+            TokenType::StarEqual => (TokenType::Star, "*"),
+            _ => (TokenType::Slash, "/"),
+        };
+        let op = Token::new(
+            token_type,
+            lexeme.to_owned(),
+            None,
+            operator.line,
+            operator.column,
+        );
+        let value = Box::new(Expr::Binary(Box::new(target.clone()), op, Box::new(value)));
+        match target {
+            Expr::Variable(t, _) => Ok(Expr::Assignment(t, value, None)),
+            Expr::Get(object, name) => Ok(Expr::Set(object, name, value)),
+            _ => Err(ParseError::new(
+                operator,
+                "Invalid assignment target.".to_owned(),
+            )),
+        }
+    }
+
+    // The else branch recurses into `ternary`, so `a ? b : c ? d : e` groups
+    // as `a ? b : (c ? d : e)`.
+    fn ternary(&mut self) -> Result<Expr, ParseError> {
+        let condition = self.or()?;
+        if !self.match_(&[TokenType::Question]) {
+            return Ok(condition);
+        }
+        let then_branch = self.expression()?;
+        self.consume(
+            TokenType::Colon,
+            &"Expect ':' after then branch of conditional expression.".to_owned(),
+        )?;
+        let else_branch = self.ternary()?;
+        Ok(Expr::Ternary(
+            Box::new(condition),
+            Box::new(then_branch),
+            Box::new(else_branch),
+        ))
+    }
+
     fn or(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.and()?;
-        while self.match_(&vec![TokenType::Or]) {
+        while self.match_(&[TokenType::Or]) {
             let operator: Token = self.previous();
             let right: Expr = self.and()?;
             expr = Expr::Logical(Box::new(expr), operator, Box::new(right))
@@ -263,7 +557,7 @@ impl Parser {
 
     fn and(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.equality()?;
-        while self.match_(&vec![TokenType::Or]) {
+        while self.match_(&[TokenType::And]) {
             let operator: Token = self.previous();
             let right: Expr = self.equality()?;
             expr = Expr::Logical(Box::new(expr), operator, Box::new(right))
@@ -273,8 +567,12 @@ impl Parser {
     }
 
     fn equality(&mut self) -> Result<Expr, ParseError> {
-        let mut expr: Expr = self.comparison()?;
-        while self.match_(&vec![TokenType::BangEqual, TokenType::EqualEqual]) {
+        let mut expr = if self.match_(&[TokenType::BangEqual, TokenType::EqualEqual]) {
+            self.missing_left_operand(Parser::comparison)?
+        } else {
+            self.comparison()?
+        };
+        while self.match_(&[TokenType::BangEqual, TokenType::EqualEqual]) {
             let operator: Token = self.previous();
             let right: Expr = self.comparison()?;
             expr = Expr::Binary(Box::new(expr), operator, Box::new(right))
@@ -283,13 +581,18 @@ impl Parser {
     }
 
     fn comparison(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.term()?;
-        while self.match_(&vec![
+        let comparison_operators = [
             TokenType::Greater,
             TokenType::GreaterEqual,
             TokenType::LessEqual,
             TokenType::Less,
-        ]) {
+        ];
+        let mut expr = if self.match_(&comparison_operators) {
+            self.missing_left_operand(Parser::term)?
+        } else {
+            self.term()?
+        };
+        while self.match_(&comparison_operators) {
             let operator: Token = self.previous();
             let right: Expr = self.term()?;
             expr = Expr::Binary(Box::new(expr), operator, Box::new(right))
@@ -298,8 +601,14 @@ impl Parser {
     }
 
     fn term(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.factor()?;
-        while self.match_(&vec![TokenType::Plus, TokenType::Minus]) {
+        // A leading '-' is a negation, so only '+' can be missing its left
+        // operand here.
+        let mut expr = if self.match_(&[TokenType::Plus]) {
+            self.missing_left_operand(Parser::factor)?
+        } else {
+            self.factor()?
+        };
+        while self.match_(&[TokenType::Plus, TokenType::Minus]) {
             let operator: Token = self.previous();
             let right: Expr = self.factor()?;
             expr = Expr::Binary(Box::new(expr), operator, Box::new(right))
@@ -308,8 +617,13 @@ impl Parser {
     }
 
     fn factor(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.unary()?;
-        while self.match_(&vec![TokenType::Slash, TokenType::Star]) {
+        let factor_operators = [TokenType::Slash, TokenType::Star, TokenType::Percent];
+        let mut expr = if self.match_(&factor_operators) {
+            self.missing_left_operand(Parser::unary)?
+        } else {
+            self.unary()?
+        };
+        while self.match_(&factor_operators) {
             let operator: Token = self.previous();
             let right: Expr = self.unary()?;
             expr = Expr::Binary(Box::new(expr), operator, Box::new(right))
@@ -317,69 +631,170 @@ impl Parser {
         Ok(expr)
     }
 
+    // Error production for a binary operator at the start of an expression,
+    // e.g. `* 3`. The right-hand operand is still parsed, at the operator's
+    // precedence, so parsing carries on after it as if it stood alone.
+    fn missing_left_operand(
+        &mut self,
+        operand: fn(&mut Parser) -> Result<Expr, ParseError>,
+    ) -> Result<Expr, ParseError> {
+        let operator = self.previous();
+        let right = operand(self)?;
+        self.errors.push(ParseError::new(
+            operator.clone(),
+            format!(
+                "Binary operator '{}' has no left-hand operand.",
+                operator.lexeme
+            ),
+        ));
+        Ok(right)
+    }
+
     fn unary(&mut self) -> Result<Expr, ParseError> {
-        if self.match_(&vec![TokenType::Bang, TokenType::Minus]) {
+        if self.match_(&[TokenType::Bang, TokenType::Minus]) {
             let operator: Token = self.previous();
             let right: Expr = self.unary()?;
+            // Fold negative numeric literals so `-123` doesn't get negated at
+            // runtime every time it's evaluated.
+            if let (TokenType::Minus, Expr::Literal(Literal::Number(n))) =
+                (operator.token_type, &right)
+            {
+                return Ok(Expr::Literal(Literal::Number(-n)));
+            }
             return Ok(Expr::Unary(operator, Box::new(right)));
         }
-        self.primary()
+        self.call()
     }
 
-    fn primary(&mut self) -> Result<Expr, ParseError> {
-        if self.match_(&vec![TokenType::False]) {
-            return Ok(Expr::Literal(Literal::Bool(true)));
+    fn call(&mut self) -> Result<Expr, ParseError> {
+        let mut expr: Expr = self.primary()?;
+        loop {
+            if self.match_(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.match_(&[TokenType::Dot]) {
+                let name = self.consume(
+                    TokenType::Identifier,
+                    &"Expect property name after '.'.".to_owned(),
+                )?;
+                expr = Expr::Get(Box::new(expr), name);
+            } else if self.match_(&[TokenType::LeftBracket]) {
+                let index = self.expression()?;
+                let bracket = self.consume(
+                    TokenType::RightBracket,
+                    &"Expect ']' after index.".to_owned(),
+                )?;
+                expr = Expr::Index(Box::new(expr), bracket, Box::new(index));
+            } else {
+                break;
+            }
+        }
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
+        let mut arguments: Vec<Expr> = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    self.errors.push(ParseError::new(
+                        self.peek().clone(),
+                        "Can't have more than 255 arguments.".to_owned(),
+                    ));
+                }
+                arguments.push(self.expression()?);
+                if !self.match_(&[TokenType::Comma]) {
+                    break;
+                }
+            }
         }
+        let paren = self.consume(
+            TokenType::RightParen,
+            &"Expect ')' after arguments.".to_owned(),
+        )?;
+        Ok(Expr::Call(Box::new(callee), paren, arguments))
+    }
 
-        if self.match_(&vec![TokenType::True]) {
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        if self.match_(&[TokenType::False]) {
             return Ok(Expr::Literal(Literal::Bool(false)));
         }
 
-        if self.match_(&vec![TokenType::Nil]) {
+        if self.match_(&[TokenType::True]) {
+            return Ok(Expr::Literal(Literal::Bool(true)));
+        }
+
+        if self.match_(&[TokenType::Nil]) {
             return Ok(Expr::Literal(Literal::Nil));
         }
 
-        if self.match_(&vec![TokenType::Number, TokenType::String]) {
+        if self.match_(&[TokenType::Number, TokenType::String]) {
             return Ok(Expr::Literal(self.previous().literal.unwrap()));
         }
 
-        if self.match_(&vec![TokenType::Identifier]) {
-            return Ok(Expr::Variable(self.previous()));
+        if self.match_(&[TokenType::This]) {
+            return Ok(Expr::This(self.previous(), None));
         }
 
-        if self.match_(&vec![TokenType::LeftParen]) {
+        if self.match_(&[TokenType::Super]) {
+            let keyword = self.previous();
+            let message = match self.classes.last() {
+                None => Some("Can't use 'super' outside of a class."),
+                Some(false) => Some("Can't use 'super' in a class with no superclass."),
+                Some(true) => None,
+            };
+            if let Some(message) = message {
+                self.errors
+                    .push(ParseError::new(keyword.clone(), message.to_owned()));
+            }
+            self.consume(TokenType::Dot, &"Expect '.' after 'super'.".to_owned())?;
+            let method = self.consume(
+                TokenType::Identifier,
+                &"Expect superclass method name.".to_owned(),
+            )?;
+            return Ok(Expr::Super(keyword, method, None));
+        }
+
+        if self.match_(&[TokenType::Identifier]) {
+            return Ok(Expr::Variable(self.previous(), None));
+        }
+
+        if self.match_(&[TokenType::LeftParen]) {
             let expr: Expr = self.expression()?;
-            let right_paren = self.consume(
-                self.peek().clone().token_type,
+            self.consume(
+                TokenType::RightParen,
                 &"Expect ')' after expression.".to_owned(),
-            );
-            match right_paren {
-                Ok(_) => return Ok(Expr::Grouping(Box::new(expr))),
-                Err(e) => return Err(e),
-            }
+            )?;
+            return Ok(Expr::Grouping(Box::new(expr)));
         }
 
-        return Err(ParseError::new(
+        Err(ParseError::new(
             self.peek().clone(),
             "Expect Expression".to_owned(),
-        ));
+        ))
     }
 
-    fn match_(&mut self, token_types: &Vec<TokenType>) -> bool {
+    fn match_(&mut self, token_types: &[TokenType]) -> bool {
         for token_type in token_types {
-            if self.check(token_type.to_owned()) {
+            if self.check(token_type) {
                 self.advance();
                 return true;
             }
         }
-        return false;
+        false
     }
 
-    fn check(&self, token_type: TokenType) -> bool {
+    fn check(&self, token_type: &TokenType) -> bool {
         if self.at_end() {
             return false;
         }
-        self.peek().token_type == token_type
+        &self.peek().token_type == token_type
+    }
+
+    fn check_next(&self, token_type: &TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => &token.token_type == token_type,
+            None => false,
+        }
     }
 
     fn advance(&mut self) -> Token {
@@ -393,8 +808,8 @@ impl Parser {
         self.peek().token_type == TokenType::Eof
     }
 
-    fn peek(&self) -> Token {
-        self.tokens[self.current].clone()
+    fn peek(&self) -> &Token {
+        &self.tokens[self.current]
     }
 
     fn previous(&self) -> Token {
@@ -402,33 +817,44 @@ impl Parser {
     }
 
     fn consume(&mut self, token_type: TokenType, message: &String) -> Result<Token, ParseError> {
-        if self.check(token_type) {
-            return Ok(self.advance());
+        if self.check(&token_type) {
+            Ok(self.advance())
         } else {
-            return Err(ParseError::new(self.peek().clone(), message.to_owned()));
+            Err(ParseError::new(self.peek().clone(), message.to_owned()))
         }
     }
 
+    // Skips to the start of the next statement so one mistake doesn't produce
+    // a cascade of errors. A missing ';' is reported at the keyword that
+    // starts the following statement, so a keyword isn't skipped; parsing it
+    // again always makes progress because the keyword itself gets consumed.
     fn syncronize(&mut self) {
-        self.advance();
+        if !self.at_statement_keyword() {
+            self.advance();
+        }
 
         while !self.at_end() {
-            if self.previous().token_type == TokenType::Semicolon {
+            if self.previous().token_type == TokenType::Semicolon || self.at_statement_keyword() {
                 return;
             }
-            match self.peek().token_type {
-                TokenType::Class
+            self.advance();
+        }
+    }
+
+    fn at_statement_keyword(&self) -> bool {
+        matches!(
+            self.peek().token_type,
+            TokenType::Class
                 | TokenType::Fun
                 | TokenType::Var
                 | TokenType::For
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => return,
-                _ => {}
-            }
-            self.advance();
-        }
+                | TokenType::Return
+                | TokenType::Break
+                | TokenType::Continue
+        )
     }
 }
 
@@ -443,3 +869,296 @@ impl ParseError {
         ParseError { token, message }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    fn parser(source: &str) -> Parser {
+        let (tokens, scan_errors) = Scanner::new(source.to_owned()).scan_partial();
+        assert!(scan_errors.is_empty(), "unexpected {:?}", scan_errors);
+        Parser::new(tokens)
+    }
+
+    #[test]
+    fn keeps_statements_around_a_broken_one() {
+        let (statements, errors) = parser("var a = 1;\nprint (;\nvar b = 2;").parse();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].token.line, 2);
+        let names: Vec<&str> = statements
+            .iter()
+            .map(|statement| match &**statement {
+                Stmt::Var(name, _) => name.lexeme.as_str(),
+                statement => panic!("expected a var, got {:?}", statement),
+            })
+            .collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn dangling_else_binds_to_the_inner_if() {
+        let (statements, errors) = parser("if (a) if (b) print 1; else print 2;").parse();
+        assert!(errors.is_empty());
+        match &*statements[0] {
+            Stmt::If(_, _, inner, None) => {
+                assert!(matches!(**inner, Stmt::If(_, _, _, Some(_))));
+            }
+            statement => panic!("expected an if without else, got {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn malformed_if_condition_is_an_error() {
+        let (statements, errors) = parser("if (1 +) print 1;\nprint 2;").parse();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].token.lexeme, ")");
+        assert!(matches!(*statements[0], Stmt::Print(_)));
+    }
+
+    #[test]
+    fn negative_number_literals_parse_as_one_literal() {
+        let mut parser = parser("-123; -x;");
+        assert!(matches!(
+            parser.expression(),
+            Ok(Expr::Literal(Literal::Number(n))) if n == -123.0
+        ));
+        parser.advance();
+        match parser.expression() {
+            Ok(Expr::Unary(op, operand)) => {
+                assert_eq!(op.token_type, TokenType::Minus);
+                assert!(matches!(*operand, Expr::Variable(_, _)));
+            }
+            expr => panic!("expected a negation, got {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn match_picks_the_first_listed_type_that_matches() {
+        let mut parser = parser("<= <");
+        assert!(!parser.match_(&[TokenType::Less, TokenType::Greater]));
+        assert!(parser.match_(&[TokenType::Less, TokenType::LessEqual]));
+        assert_eq!(parser.previous().token_type, TokenType::LessEqual);
+        assert!(parser.check(&TokenType::Less));
+        assert!(!parser.check(&TokenType::LessEqual));
+    }
+
+    #[test]
+    fn operators_keep_their_precedence() {
+        let (statements, errors) = parser("1 + 2 * 3 == 7 and !false;").parse();
+        assert!(errors.is_empty());
+        let expr = match &*statements[0] {
+            Stmt::Expr(expr) => expr,
+            statement => panic!("expected an expression, got {:?}", statement),
+        };
+        let (equality, right) = match &**expr {
+            Expr::Logical(lhs, op, rhs) if op.token_type == TokenType::And => (lhs, rhs),
+            expr => panic!("expected 'and', got {:?}", expr),
+        };
+        assert!(matches!(**right, Expr::Unary(_, _)));
+        let sum = match &**equality {
+            Expr::Binary(lhs, op, _) if op.token_type == TokenType::EqualEqual => lhs,
+            expr => panic!("expected '==', got {:?}", expr),
+        };
+        match &**sum {
+            Expr::Binary(_, op, product) if op.token_type == TokenType::Plus => {
+                assert!(
+                    matches!(&**product, Expr::Binary(_, op, _) if op.token_type == TokenType::Star)
+                );
+            }
+            expr => panic!("expected '+', got {:?}", expr),
+        }
+    }
+
+    fn expression(source: &str) -> Expr {
+        parser(source).expression().expect("source should parse")
+    }
+
+    fn logical(expr: &Expr, token_type: TokenType) -> (&Expr, &Expr) {
+        match expr {
+            Expr::Logical(lhs, op, rhs) if op.token_type == token_type => (lhs, rhs),
+            expr => panic!("expected {:?}, got {:?}", token_type, expr),
+        }
+    }
+
+    #[test]
+    fn and_parses_as_a_logical_and() {
+        let expr = expression("true and false");
+        let (lhs, rhs) = logical(&expr, TokenType::And);
+        assert!(matches!(lhs, Expr::Literal(Literal::Bool(true))));
+        assert!(matches!(rhs, Expr::Literal(Literal::Bool(false))));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expr = expression("a or b and c");
+        let (_, rhs) = logical(&expr, TokenType::Or);
+        logical(rhs, TokenType::And);
+        let expr = expression("a and b or c");
+        let (lhs, _) = logical(&expr, TokenType::Or);
+        logical(lhs, TokenType::And);
+    }
+
+    #[test]
+    fn true_and_false_literals() {
+        assert!(matches!(
+            expression("true"),
+            Expr::Literal(Literal::Bool(true))
+        ));
+        assert!(matches!(
+            expression("false"),
+            Expr::Literal(Literal::Bool(false))
+        ));
+    }
+
+    #[test]
+    fn unclosed_grouping_is_an_error() {
+        let (_, errors) = parser("print (1 + 2;").parse();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Expect ')' after expression.");
+        assert_eq!(errors[0].token.lexeme, ";");
+        assert!(matches!(expression("(1 + 2)"), Expr::Grouping(_)));
+    }
+
+    #[test]
+    fn parses_a_function_declaration() {
+        let (statements, errors) = parser("fun add(a, b) { print a; return a + b; }").parse();
+        assert!(errors.is_empty());
+        match &*statements[0] {
+            Stmt::Function(name, params, body) => {
+                assert_eq!(name.lexeme, "add");
+                let params: Vec<&str> = params.iter().map(|p| p.lexeme.as_str()).collect();
+                assert_eq!(params, ["a", "b"]);
+                assert_eq!(body.len(), 2);
+            }
+            statement => panic!("expected a function, got {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn functions_take_at_most_255_parameters() {
+        let params = |n: usize| {
+            (0..n)
+                .map(|i| format!("p{}", i))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let (_, errors) = parser(&format!("fun f({}) {{}}", params(255))).parse();
+        assert!(errors.is_empty());
+        let (statements, errors) = parser(&format!("fun f({}) {{}}", params(256))).parse();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Can't have more than 255 parameters.");
+        assert_eq!(errors[0].token.lexeme, "p255");
+        assert_eq!(statements.len(), 1);
+    }
+
+    #[test]
+    fn calls_chain_and_keep_the_closing_paren() {
+        match expression("f(1)(2, 3)") {
+            Expr::Call(callee, paren, arguments) => {
+                assert_eq!(paren.lexeme, ")");
+                assert_eq!(arguments.len(), 2);
+                assert!(matches!(*callee, Expr::Call(_, _, ref inner) if inner.len() == 1));
+            }
+            expr => panic!("expected a call, got {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn calls_take_at_most_255_arguments() {
+        let arguments = |n: usize| vec!["1"; n].join(", ");
+        let (_, errors) = parser(&format!("f({});", arguments(255))).parse();
+        assert!(errors.is_empty());
+        let (_, errors) = parser(&format!("f({});", arguments(256))).parse();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Can't have more than 255 arguments.");
+    }
+
+    #[test]
+    fn reports_every_broken_statement_once() {
+        let (statements, errors) =
+            parser("var a = 1\nprint a;\nprint (a;\nvar = 2;\nprint a + 1;").parse();
+        let lines: Vec<i32> = errors.iter().map(|error| error.token.line).collect();
+        assert_eq!(lines, [2, 3, 4]);
+        assert_eq!(errors[0].message, "Expect ';' after variable declaration.");
+        assert_eq!(statements.len(), 2);
+    }
+
+    #[test]
+    fn ternary_is_right_associative() {
+        match expression("a ? b : c ? d : e") {
+            Expr::Ternary(condition, _, else_branch) => {
+                assert!(matches!(*condition, Expr::Variable(ref name, _) if name.lexeme == "a"));
+                assert!(matches!(*else_branch, Expr::Ternary(_, _, _)));
+            }
+            expr => panic!("expected a ternary, got {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn for_in_parses_the_loop_variable_and_iterable() {
+        let (statements, errors) = parser("for (word in split(s, \" \")) print word;").parse();
+        assert!(errors.is_empty());
+        match &*statements[0] {
+            Stmt::ForIn(_, name, iterable, body) => {
+                assert_eq!(name.lexeme, "word");
+                assert!(matches!(**iterable, Expr::Call(_, _, _)));
+                assert!(matches!(**body, Stmt::Print(_)));
+            }
+            statement => panic!("expected a for-in, got {:?}", statement),
+        }
+        // Without `in` it's still a C-style loop.
+        let (statements, errors) = parser("for (i = 0; i < 3; i = i + 1) print i;").parse();
+        assert!(errors.is_empty());
+        match &*statements[0] {
+            Stmt::Block(statements) => {
+                assert!(matches!(statements[1], Stmt::While(_, _, _, Some(_))))
+            }
+            statement => panic!("expected a block, got {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn compound_assignment_desugars_to_an_assignment() {
+        match expression("x -= 2") {
+            Expr::Assignment(name, value, _) => {
+                assert_eq!(name.lexeme, "x");
+                match *value {
+                    Expr::Binary(lhs, op, _) => {
+                        assert!(matches!(*lhs, Expr::Variable(ref name, _) if name.lexeme == "x"));
+                        assert_eq!(op.token_type, TokenType::Minus);
+                    }
+                    expr => panic!("expected a subtraction, got {:?}", expr),
+                }
+            }
+            expr => panic!("expected an assignment, got {:?}", expr),
+        }
+        assert!(matches!(expression("a.b *= 3"), Expr::Set(_, _, _)));
+        let error = parser("1 += 2").expression().unwrap_err();
+        assert_eq!(error.message, "Invalid assignment target.");
+        assert_eq!(error.token.lexeme, "+=");
+    }
+
+    #[test]
+    fn leading_binary_operator_reports_a_missing_left_operand() {
+        for op in &["==", "!=", "<", "<=", ">", ">=", "+", "*", "/", "%"] {
+            let (_, errors) = parser(&format!("print {} 3;", op)).parse();
+            assert_eq!(errors.len(), 1, "{}", op);
+            assert_eq!(errors[0].token.lexeme, *op);
+            assert_eq!(
+                errors[0].message,
+                format!("Binary operator '{}' has no left-hand operand.", op)
+            );
+        }
+        let (_, errors) = parser("print -3;").parse();
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn parsing_continues_after_a_missing_left_operand() {
+        // The right operand is consumed, so the statement ends cleanly.
+        let (statements, errors) = parser("var a = * 2 + 1;\nprint a;").parse();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(statements.len(), 2);
+    }
+}
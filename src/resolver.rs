@@ -0,0 +1,321 @@
+use crate::ast::{Expr, Stmt};
+use crate::parser::ParseError;
+use crate::token::Token;
+use std::collections::HashMap;
+use std::mem;
+
+// A static pass run between parsing and interpreting. For every variable
+// reference it records how many environments up from the innermost one its
+// declaration lives, so the interpreter binds each name to the scope it was
+// written in rather than to whatever happens to be in the chain at runtime.
+// References left as `None` are globals.
+//
+// The scopes pushed here have to mirror the environments the interpreter
+// creates: one per block, one per `for`-`in` iteration, one per call
+// holding the parameters and the body, one binding `this` around each
+// class's methods and, for subclasses, one binding `super` around that.
+#[derive(Default)]
+pub struct Resolver {
+    // Each name maps to whether its initializer has finished resolving.
+    scopes: Vec<HashMap<String, bool>>,
+    // Whether the code being resolved is inside a class body, so `this` can
+    // be rejected anywhere else.
+    current_class: ClassType,
+    errors: Vec<ParseError>,
+}
+
+#[derive(Clone, Copy, PartialEq, Default)]
+enum ClassType {
+    #[default]
+    None,
+    Class,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            scopes: Vec::new(),
+            current_class: ClassType::None,
+            errors: Vec::new(),
+        }
+    }
+
+    // Returns every error found; the statements are only fit to run if there
+    // were none.
+    pub fn resolve(&mut self, statements: &mut [Box<Stmt>]) -> Vec<ParseError> {
+        for statement in statements {
+            self.statement(statement);
+        }
+        mem::take(&mut self.errors)
+    }
+
+    fn statement(&mut self, statement: &mut Stmt) {
+        match statement {
+            Stmt::Block(statements) => {
+                self.begin_scope();
+                for statement in statements {
+                    self.statement(statement);
+                }
+                self.end_scope();
+            }
+            Stmt::Expr(expr) | Stmt::Print(expr) => self.expression(expr),
+            Stmt::Var(name, initializer) => {
+                self.declare(name);
+                if let Some(initializer) = &mut **initializer {
+                    self.expression(initializer);
+                }
+                self.define(&name.lexeme);
+            }
+            Stmt::If(_, condition, then_branch, else_branch) => {
+                self.expression(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Stmt::While(_, condition, body, increment) => {
+                self.expression(condition);
+                self.statement(body);
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                }
+            }
+            Stmt::ForIn(_, name, iterable, body) => {
+                self.expression(iterable);
+                self.begin_scope();
+                self.declare(name);
+                self.define(&name.lexeme);
+                self.statement(body);
+                self.end_scope();
+            }
+            Stmt::Function(name, params, body) => {
+                // Defined first so the body can refer to itself recursively.
+                self.declare(name);
+                self.define(&name.lexeme);
+                self.function(params, body);
+            }
+            Stmt::Return(_, value) => {
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => {}
+            Stmt::Class(name, superclass, methods) => {
+                let enclosing_class = mem::replace(&mut self.current_class, ClassType::Class);
+                self.declare(name);
+                self.define(&name.lexeme);
+                if let Some(superclass) = superclass {
+                    self.expression(superclass);
+                    self.begin_scope();
+                    self.define("super");
+                }
+                self.begin_scope();
+                self.define("this");
+                for method in methods {
+                    if let Stmt::Function(_, params, body) = method {
+                        self.function(params, body);
+                    }
+                }
+                self.end_scope();
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+                self.current_class = enclosing_class;
+            }
+        }
+    }
+
+    fn function(&mut self, params: &[Token], body: &mut [Stmt]) {
+        self.begin_scope();
+        for param in params {
+            self.declare(param);
+            self.define(&param.lexeme);
+        }
+        for statement in body {
+            self.statement(statement);
+        }
+        self.end_scope();
+    }
+
+    fn expression(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Literal(_) => {}
+            Expr::Variable(name, depth) => {
+                let declared_only = match self.scopes.last() {
+                    Some(scope) => scope.get(&name.lexeme) == Some(&false),
+                    None => false,
+                };
+                if declared_only {
+                    self.errors.push(ParseError::new(
+                        name.clone(),
+                        "Can't read local variable in its own initializer.".to_owned(),
+                    ));
+                }
+                *depth = self.resolve_local(name);
+            }
+            Expr::Assignment(name, value, depth) => {
+                self.expression(value);
+                *depth = self.resolve_local(name);
+            }
+            Expr::Binary(lhs, _, rhs) | Expr::Logical(lhs, _, rhs) => {
+                self.expression(lhs);
+                self.expression(rhs);
+            }
+            Expr::Ternary(condition, then_branch, else_branch) => {
+                self.expression(condition);
+                self.expression(then_branch);
+                self.expression(else_branch);
+            }
+            Expr::Unary(_, e) | Expr::Grouping(e) => self.expression(e),
+            Expr::Call(callee, _, arguments) => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+            Expr::Get(object, _) => self.expression(object),
+            Expr::Index(object, _, index) => {
+                self.expression(object);
+                self.expression(index);
+            }
+            Expr::Set(object, _, value) => {
+                self.expression(value);
+                self.expression(object);
+            }
+            Expr::This(keyword, depth) => {
+                if self.current_class == ClassType::None {
+                    self.errors.push(ParseError::new(
+                        keyword.clone(),
+                        "Cannot use 'this' outside of a class.".to_owned(),
+                    ));
+                }
+                *depth = self.resolve_local(keyword)
+            }
+            Expr::Super(keyword, _, depth) => *depth = self.resolve_local(keyword),
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    // Globals aren't tracked; only names in a local scope are resolved. Unlike
+    // globals, a local can't be declared twice in the same scope, though it
+    // can shadow one from an enclosing scope. A declared name isn't readable
+    // until it's defined.
+    fn declare(&mut self, name: &Token) {
+        let scope = match self.scopes.last_mut() {
+            Some(scope) => scope,
+            None => return,
+        };
+        if scope.contains_key(&name.lexeme) {
+            self.errors.push(ParseError::new(
+                name.clone(),
+                "Already a variable with this name in this scope.".to_owned(),
+            ));
+        }
+        scope.insert(name.lexeme.clone(), false);
+    }
+
+    fn define(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_owned(), true);
+        }
+    }
+
+    fn resolve_local(&self, name: &Token) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(&name.lexeme))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    // Resolves `source`, returning the depth of each variable it prints (in
+    // source order, looking inside blocks) and the messages of any errors.
+    fn resolve(source: &str) -> (Vec<Option<usize>>, Vec<String>) {
+        let tokens = Scanner::new(source.to_owned()).scan_tokens();
+        let (mut statements, errors) = Parser::new(tokens).parse();
+        assert!(errors.is_empty(), "{:?}", errors);
+        let errors = Resolver::new().resolve(&mut statements);
+        (
+            printed_depths(&statements),
+            errors.into_iter().map(|error| error.message).collect(),
+        )
+    }
+
+    fn printed_depths(statements: &[Box<Stmt>]) -> Vec<Option<usize>> {
+        fn collect(statement: &Stmt, depths: &mut Vec<Option<usize>>) {
+            match statement {
+                Stmt::Print(expr) => match &**expr {
+                    Expr::Variable(_, depth) => depths.push(*depth),
+                    expr => panic!("expected a variable, got {:?}", expr),
+                },
+                Stmt::Block(statements) => {
+                    for statement in statements {
+                        collect(statement, depths);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let mut depths = Vec::new();
+        for statement in statements {
+            collect(statement, &mut depths);
+        }
+        depths
+    }
+
+    #[test]
+    fn binds_each_reference_to_its_declaring_scope() {
+        let (depths, errors) =
+            resolve("var g; { var a; print a; { var b; print a; print b; print g; } }");
+        assert!(errors.is_empty());
+        assert_eq!(depths, [Some(0), Some(1), Some(0), None]);
+    }
+
+    #[test]
+    fn a_later_shadow_does_not_rebind_an_earlier_reference() {
+        let (depths, _) = resolve("{ var a; { print a; var a; print a; } }");
+        assert_eq!(depths, [Some(1), Some(0)]);
+    }
+
+    #[test]
+    fn redeclaring_a_local_in_the_same_scope_is_an_error() {
+        let message = "Already a variable with this name in this scope.";
+        assert_eq!(resolve("{ var a; var a; }").1, [message]);
+        assert_eq!(resolve("fun f(a, a) {}").1, [message]);
+        assert!(resolve("{ var a; { var a; } }").1.is_empty());
+        assert!(resolve("var a; var a;").1.is_empty());
+    }
+
+    #[test]
+    fn reading_a_local_in_its_own_initializer_is_an_error() {
+        let message = "Can't read local variable in its own initializer.";
+        assert_eq!(resolve("{ var a = a; }").1, [message]);
+        assert_eq!(resolve("var a; { var a = a + 1; }").1, [message]);
+        assert!(resolve("var a = a;").1.is_empty());
+        assert!(resolve("{ var a; { var b = a; } }").1.is_empty());
+    }
+
+    #[test]
+    fn this_is_only_allowed_inside_a_class() {
+        let message = "Cannot use 'this' outside of a class.";
+        assert_eq!(resolve("fun f() { return this; }").1, [message]);
+        assert_eq!(
+            resolve("class A { m() { fun f() { return this; } } }").1,
+            Vec::<String>::new()
+        );
+        assert_eq!(resolve("class A {} fun g() { return this; }").1, [message]);
+    }
+}
@@ -8,12 +8,15 @@ lazy_static! {
     static ref KEYWORDS: HashMap<String, TokenType> = {
         let mut m = HashMap::new();
         m.insert("and".to_owned(), TokenType::And);
+        m.insert("break".to_owned(), TokenType::Break);
         m.insert("class".to_owned(), TokenType::Class);
+        m.insert("continue".to_owned(), TokenType::Continue);
         m.insert("else".to_owned(), TokenType::Else);
         m.insert("false".to_owned(), TokenType::False);
         m.insert("for".to_owned(), TokenType::For);
         m.insert("fun".to_owned(), TokenType::Fun);
         m.insert("if".to_owned(), TokenType::If);
+        m.insert("in".to_owned(), TokenType::In);
         m.insert("nil".to_owned(), TokenType::Nil);
         m.insert("or".to_owned(), TokenType::Or);
         m.insert("print".to_owned(), TokenType::Print);
@@ -30,32 +33,75 @@ lazy_static! {
 
 #[derive(Default)]
 pub struct Scanner {
-    source: String,
+    source: Vec<char>,
     tokens: Vec<Token>,
     start: usize,
     current: usize,
     line: i32,
+    // Columns are 1-based, reset at the start of every line and count each
+    // character (including tabs) as one.
+    column: i32,
+    start_column: i32,
+    errors: Vec<ScanError>,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Self {
+        // Some editors prefix UTF-8 files with a byte order mark. It is only
+        // skipped at the very start; anywhere else it is an unexpected character.
+        let source = source.strip_prefix('\u{FEFF}').unwrap_or(&source);
         Scanner {
-            source,
+            source: source.chars().collect(),
             tokens: Vec::new(),
             start: 0,
             current: 0,
             line: 1,
+            column: 1,
+            start_column: 1,
+            errors: Vec::new(),
         }
     }
 
     pub fn scan_tokens(&mut self) -> Vec<Token> {
+        let (tokens, errors) = self.scan_partial();
+        let source: String = self.source.iter().collect();
+        for error in errors {
+            Lox::scan_error(&source, error);
+        }
+        tokens
+    }
+
+    // Scans the whole source, returning the errors found along the way instead
+    // of reporting them. Malformed input is skipped, so the tokens are still
+    // usable for parsing.
+    pub fn scan_partial(&mut self) -> (Vec<Token>, Vec<ScanError>) {
         while !self.at_end() {
             self.start = self.current;
+            self.start_column = self.column;
             self.scan_token();
         }
-        let end_of_file = Token::new(TokenType::Eof, "".to_owned(), None, self.line);
+        let end_of_file = Token::new(TokenType::Eof, "".to_owned(), None, self.line, self.column);
         self.tokens.push(end_of_file);
-        self.tokens.clone()
+        (self.tokens.clone(), self.errors.drain(..).collect())
+    }
+
+    fn error(&mut self, line: i32, column: i32, message: String) {
+        self.errors.push(ScanError {
+            line,
+            column,
+            message,
+            unterminated: false,
+        });
+    }
+
+    // An error caused only by the source ending inside a token.
+    fn unterminated(&mut self, line: i32, column: i32, message: String) {
+        self.errors.push(ScanError {
+            line,
+            column,
+            message,
+            unterminated: true,
+        });
     }
 
     fn scan_token(&mut self) {
@@ -65,12 +111,26 @@ impl Scanner {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
+            ':' => self.add_token(TokenType::Colon),
+            '?' => self.add_token(TokenType::Question),
             '.' => self.add_token(TokenType::Dot),
-            '-' => self.add_token(TokenType::Minus),
-            '+' => self.add_token(TokenType::Plus),
+            '-' => match self.match_('=') {
+                true => self.add_token(TokenType::MinusEqual),
+                _ => self.add_token(TokenType::Minus),
+            },
+            '+' => match self.match_('=') {
+                true => self.add_token(TokenType::PlusEqual),
+                _ => self.add_token(TokenType::Plus),
+            },
             ';' => self.add_token(TokenType::Semicolon),
-            '*' => self.add_token(TokenType::Star),
+            '*' => match self.match_('=') {
+                true => self.add_token(TokenType::StarEqual),
+                _ => self.add_token(TokenType::Star),
+            },
+            '%' => self.add_token(TokenType::Percent),
             '!' => match self.match_('=') {
                 true => self.add_token(TokenType::BangEqual),
                 _ => self.add_token(TokenType::Bang),
@@ -88,39 +148,74 @@ impl Scanner {
                 true => self.add_token(TokenType::GreaterEqual),
                 _ => self.add_token(TokenType::Greater),
             },
-            '/' => match self.match_('/') {
-                true => {
+            '/' => {
+                if self.match_('/') {
                     while self.peek() != '\n' && !self.at_end() {
                         self.advance();
                     }
+                } else if self.match_('*') {
+                    self.block_comment();
+                } else if self.match_('=') {
+                    self.add_token(TokenType::SlashEqual);
+                } else {
+                    self.add_token(TokenType::Slash);
                 }
-                _ => self.add_token(TokenType::Slash),
-            },
-            ' ' | '\r' | '\t' => {}
-            '\n' => self.line += 1,
+            }
+            ' ' | '\r' | '\t' | '\n' => {}
             '"' => self.string(),
             _ => {
-                if c.is_digit(10) {
+                if c.is_ascii_digit() {
                     self.number();
                 } else if c.is_alphabetic() {
                     self.identifier();
                 } else {
-                    Lox::error(self.line, "Unexpected character".to_owned())
+                    self.error(
+                        self.line,
+                        self.start_column,
+                        "Unexpected character".to_owned(),
+                    )
                 }
             }
         }
     }
 
+    // Block comments nest, so the comment only ends once every `/*` inside it
+    // has been matched by a `*/`.
+    fn block_comment(&mut self) {
+        let start_line = self.line;
+        let mut depth = 1;
+        while !self.at_end() {
+            if self.peek() == '/' && self.peek_next() == '*' {
+                self.advance();
+                self.advance();
+                depth += 1;
+            } else if self.peek() == '*' && self.peek_next() == '/' {
+                self.advance();
+                self.advance();
+                depth -= 1;
+                if depth == 0 {
+                    return;
+                }
+            } else {
+                self.advance();
+            }
+        }
+
+        self.unterminated(
+            start_line,
+            self.start_column,
+            "Unterminated block comment.".to_owned(),
+        );
+    }
+
+    // Identifiers start with a letter and may contain `_` after that, as in
+    // `random_int`. A leading `_` is still an unexpected character, so `_5`
+    // isn't taken for a name.
     fn identifier(&mut self) {
-        while self.peek().is_alphanumeric() {
+        while self.peek().is_alphanumeric() || self.peek() == '_' {
             self.advance();
         }
-        let text = self
-            .source
-            .chars()
-            .skip(self.start)
-            .take(self.current - self.start)
-            .collect::<String>();
+        let text = self.lexeme();
         let keyword_lookup = KEYWORDS.get(&text);
         let token_type = match keyword_lookup {
             Some(t) => t,
@@ -130,78 +225,200 @@ impl Scanner {
     }
 
     fn number(&mut self) {
-        while self.peek().is_digit(10) {
-            self.advance();
+        let first = self.source[self.start];
+        if first == '0' && (self.peek() == 'x' || self.peek() == 'b') {
+            let radix = if self.advance() == 'x' { 16 } else { 2 };
+            self.radix_number(radix);
+            return;
         }
 
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
+        if !self.digits(10) {
+            return self.malformed_number();
+        }
+
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             self.advance();
-            while self.peek().is_digit(10) {
-                self.advance();
+            if !self.digits(10) {
+                return self.malformed_number();
             }
         }
-        let value: Literal = Literal::Number(
-            self.source
-                .chars()
-                .skip(self.start)
-                .take(self.current - self.start)
-                .collect::<String>()
-                .parse::<f64>()
-                .unwrap(),
-        );
+        let value: Literal =
+            Literal::Number(self.lexeme().replace('_', "").parse::<f64>().unwrap());
 
         self.add_full_token(TokenType::Number, Some(value));
     }
 
+    // Scans a run of digits in `radix`, allowing single underscores between
+    // digits as separators. A leading, trailing or doubled underscore is an
+    // error, in which case the rest of the malformed literal is skipped.
+    fn digits(&mut self, radix: u32) -> bool {
+        loop {
+            if self.peek().is_digit(radix) {
+                self.advance();
+            } else if self.peek() == '_' {
+                self.advance();
+                if !self.peek().is_digit(radix) {
+                    self.error(
+                        self.line,
+                        self.column,
+                        "Digit separator '_' must be between digits.".to_owned(),
+                    );
+                    while self.peek().is_alphanumeric()
+                        || self.peek() == '_'
+                        || (self.peek() == '.' && self.peek_next().is_ascii_digit())
+                    {
+                        self.advance();
+                    }
+                    return false;
+                }
+            } else {
+                return true;
+            }
+        }
+    }
+
+    // Scans the digits of a `0x` or `0b` literal; the prefix has already been
+    // consumed.
+    fn radix_number(&mut self, radix: u32) {
+        if !self.digits(radix) {
+            return self.malformed_number();
+        }
+        let text = self.lexeme().replace('_', "");
+        let digits = &text[2..];
+        if digits.is_empty() {
+            self.error(
+                self.line,
+                self.column,
+                format!("Expect digits after '{}'.", text),
+            );
+            return self.malformed_number();
+        }
+        match u64::from_str_radix(digits, radix) {
+            Ok(n) => self.add_full_token(TokenType::Number, Some(Literal::Number(n as f64))),
+            Err(_) => {
+                self.error(
+                    self.line,
+                    self.column,
+                    format!("Number literal '{}' is too large.", text),
+                );
+                self.malformed_number();
+            }
+        }
+    }
+
+    // Still emits a number token for a literal that was reported as
+    // malformed, so the parser sees an operand where one was written and
+    // doesn't add errors of its own. The scan error keeps the program from
+    // running, so the value is never used.
+    fn malformed_number(&mut self) {
+        self.add_full_token(TokenType::Number, Some(Literal::Number(f64::NAN)));
+    }
+
     fn match_(&mut self, expected: char) -> bool {
         if self.at_end() {
             return false;
         }
 
-        if self.source.chars().nth(self.current).unwrap() != expected {
+        if self.source[self.current] != expected {
             return false;
         }
 
-        self.current += 1;
+        self.advance();
         true
     }
 
     fn string(&mut self) {
+        let mut value = String::new();
         while self.peek() != '"' && !self.at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            match self.advance() {
+                '\\' if !self.at_end() => self.escape(&mut value),
+                c => value.push(c),
             }
-            self.advance();
         }
 
         if self.at_end() {
-            Lox::error(self.line, "Unterminated string.".to_owned());
+            self.unterminated(self.line, self.column, "Unterminated string.".to_owned());
             return;
         }
 
         self.advance();
-        let text = self
-            .source
-            .chars()
-            .skip(self.start)
-            .take((self.current) - (self.start))
-            .collect::<String>();
-        let text_without_quotes = text
-            .chars()
-            .skip(1)
-            .take(text.len() - 2)
-            .collect::<String>();
+        self.add_full_token(TokenType::String, Some(Literal::String(value)));
+    }
+
+    // Decodes the escape sequence following a backslash into `value`.
+    fn escape(&mut self, value: &mut String) {
+        match self.advance() {
+            'n' => value.push('\n'),
+            't' => value.push('\t'),
+            'r' => value.push('\r'),
+            '\\' => value.push('\\'),
+            '"' => value.push('"'),
+            'u' => self.unicode_escape(value),
+            'x' => self.hex_escape(value),
+            c => self.error(
+                self.line,
+                self.column,
+                format!("Unknown escape sequence '\\{}'.", c),
+            ),
+        }
+    }
 
-        let value: Literal = Literal::String(text_without_quotes.clone());
+    // Decodes a `\xNN` escape of exactly two hex digits, giving the character
+    // U+0000 to U+00FF.
+    fn hex_escape(&mut self, value: &mut String) {
+        let mut digits = String::new();
+        while digits.chars().count() < 2 && !self.at_end() && self.peek() != '"' {
+            digits.push(self.advance());
+        }
+        let well_formed = digits.len() == 2 && digits.chars().all(|c| c.is_ascii_hexdigit());
+        match u8::from_str_radix(&digits, 16).ok().filter(|_| well_formed) {
+            Some(byte) => value.push(char::from(byte)),
+            None => self.error(
+                self.line,
+                self.column,
+                format!("Invalid hex escape '\\x{}'.", digits),
+            ),
+        }
+    }
 
-        self.add_full_token(TokenType::String, Some(value));
+    // Decodes a `\u{XXXX}` escape of one to six hex digits naming a Unicode
+    // scalar value.
+    fn unicode_escape(&mut self, value: &mut String) {
+        if !self.match_('{') {
+            self.error(self.line, self.column, "Expect '{' after '\\u'.".to_owned());
+            return;
+        }
+        let mut digits = String::new();
+        while self.peek().is_ascii_hexdigit() {
+            digits.push(self.advance());
+        }
+        if !self.match_('}') {
+            self.error(
+                self.line,
+                self.column,
+                "Expect '}' after unicode escape digits.".to_owned(),
+            );
+            return;
+        }
+        let code_point = u32::from_str_radix(&digits, 16)
+            .ok()
+            .filter(|_| digits.len() <= 6)
+            .and_then(char::from_u32);
+        match code_point {
+            Some(c) => value.push(c),
+            None => self.error(
+                self.line,
+                self.column,
+                format!("Invalid unicode escape '\\u{{{}}}'.", digits),
+            ),
+        }
     }
 
     fn peek(&self) -> char {
         if self.at_end() {
             '\0'
         } else {
-            self.source.chars().nth(self.current).unwrap()
+            self.source[self.current]
         }
     }
 
@@ -209,7 +426,7 @@ impl Scanner {
         if self.current + 1 >= self.source.len() {
             '\0'
         } else {
-            self.source.chars().nth(self.current + 1).unwrap()
+            self.source[self.current + 1]
         }
     }
 
@@ -218,24 +435,350 @@ impl Scanner {
     }
 
     fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current as usize).unwrap();
+        let c = self.source[self.current];
         self.current += 1;
-        c as char
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        c
     }
 
     fn add_token(&mut self, token_type: TokenType) {
         self.add_full_token(token_type, Some(Literal::Nil));
     }
 
+    fn lexeme(&self) -> String {
+        self.source[self.start..self.current].iter().collect()
+    }
+
     fn add_full_token(&mut self, token_type: TokenType, literal: Option<Literal>) {
-        let text: String = self
-            .source
-            .chars()
-            .skip(self.start)
-            .take(self.current - self.start)
-            .collect();
-
-        self.tokens
-            .push(Token::new(token_type, text, literal, self.line))
+        let text: String = self.lexeme();
+
+        self.tokens.push(Token::new(
+            token_type,
+            text,
+            literal,
+            self.line,
+            self.start_column,
+        ))
+    }
+}
+
+#[derive(Debug)]
+pub struct ScanError {
+    pub line: i32,
+    pub column: i32,
+    pub message: String,
+    // Set when a string or block comment is still open at the end of the
+    // source, i.e. more input could make the error go away.
+    pub unterminated: bool,
+}
+
+// Scans `source` and strips position information from the tokens, giving a
+// stable form for comparing scanner output in table-driven tests. Errors are
+// dropped rather than reported, so snapshotting malformed input prints
+// nothing and leaves the global error flag alone.
+pub fn tokens_of(source: &str) -> Vec<(TokenType, String, Option<Literal>)> {
+    let (tokens, _) = Scanner::new(source.to_owned()).scan_partial();
+    tokens
+        .into_iter()
+        .map(|token| (token.token_type, token.lexeme, token.literal))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(source: &str) -> Vec<(String, i32, i32)> {
+        let (tokens, errors) = Scanner::new(source.to_owned()).scan_partial();
+        assert!(errors.is_empty(), "unexpected {:?}", errors);
+        tokens
+            .into_iter()
+            .map(|token| (token.lexeme, token.line, token.column))
+            .collect()
+    }
+
+    #[test]
+    fn columns_are_one_based_and_reset_each_line() {
+        assert_eq!(
+            positions("var a;\n  print a;\n\tx"),
+            [
+                ("var".to_owned(), 1, 1),
+                ("a".to_owned(), 1, 5),
+                (";".to_owned(), 1, 6),
+                ("print".to_owned(), 2, 3),
+                ("a".to_owned(), 2, 9),
+                (";".to_owned(), 2, 10),
+                ("x".to_owned(), 3, 2),
+                ("".to_owned(), 3, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_at_the_start_of_a_line_are_column_one() {
+        let (_, errors) = Scanner::new("1;\n@".to_owned()).scan_partial();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].line, errors[0].column), (2, 1));
+    }
+
+    #[test]
+    fn skips_a_leading_byte_order_mark() {
+        assert_eq!(tokens_of("\u{FEFF}print 1;"), tokens_of("print 1;"));
+        let (_, errors) = Scanner::new("print 1;\u{FEFF}".to_owned()).scan_partial();
+        assert_eq!(errors.len(), 1);
+    }
+
+    fn number(lexeme: &str, n: f64) -> (TokenType, String, Option<Literal>) {
+        (
+            TokenType::Number,
+            lexeme.to_owned(),
+            Some(Literal::Number(n)),
+        )
+    }
+
+    fn string(lexeme: &str, s: &str) -> (TokenType, String, Option<Literal>) {
+        let value = Some(Literal::String(s.to_owned()));
+        (TokenType::String, lexeme.to_owned(), value)
+    }
+
+    fn eof() -> (TokenType, String, Option<Literal>) {
+        (TokenType::Eof, "".to_owned(), None)
+    }
+
+    #[test]
+    fn number_literal_snapshots() {
+        let cases = vec![
+            ("42", number("42", 42.0)),
+            ("3.25", number("3.25", 3.25)),
+            ("1_000_000", number("1_000_000", 1e6)),
+            ("0x1F", number("0x1F", 31.0)),
+            ("0xff_ff", number("0xff_ff", 65535.0)),
+            ("0b1010", number("0b1010", 10.0)),
+            ("0b1_0", number("0b1_0", 2.0)),
+        ];
+        for (source, token) in cases {
+            assert_eq!(tokens_of(source), [token, eof()], "scanning {}", source);
+        }
+    }
+
+    #[test]
+    fn string_literal_snapshots() {
+        let cases = vec![
+            (r#""plain""#, string(r#""plain""#, "plain")),
+            (r#""a\tb\n""#, string(r#""a\tb\n""#, "a\tb\n")),
+            (r#""say \"hi\"""#, string(r#""say \"hi\"""#, "say \"hi\"")),
+            (
+                r#""\u{48}\u{1F600}""#,
+                string(r#""\u{48}\u{1F600}""#, "H\u{1F600}"),
+            ),
+        ];
+        for (source, token) in cases {
+            assert_eq!(tokens_of(source), [token, eof()], "scanning {}", source);
+        }
+    }
+
+    #[test]
+    fn trailing_dot_is_not_part_of_a_number() {
+        assert_eq!(
+            tokens_of("1."),
+            [
+                number("1", 1.0),
+                (TokenType::Dot, ".".to_owned(), Some(Literal::Nil)),
+                eof()
+            ]
+        );
+    }
+
+    fn errors_of(source: &str) -> Vec<String> {
+        let (_, errors) = Scanner::new(source.to_owned()).scan_partial();
+        errors.into_iter().map(|error| error.message).collect()
+    }
+
+    #[test]
+    fn hex_and_unicode_escapes() {
+        assert_eq!(
+            tokens_of(r#""\x41\x7e\xE9""#),
+            [string(r#""\x41\x7e\xE9""#, "A~\u{e9}"), eof()]
+        );
+        assert_eq!(
+            tokens_of(r#""\u{1F600}!""#),
+            [string(r#""\u{1F600}!""#, "\u{1F600}!"), eof()]
+        );
+    }
+
+    #[test]
+    fn malformed_escapes_quote_the_offending_text() {
+        assert_eq!(errors_of(r#""\xZZ""#), ["Invalid hex escape '\\xZZ'."]);
+        assert_eq!(errors_of(r#""\x4""#), ["Invalid hex escape '\\x4'."]);
+        assert_eq!(errors_of(r#""\u{}""#), ["Invalid unicode escape '\\u{}'."]);
+        assert_eq!(
+            errors_of(r#""\u{D800}""#),
+            ["Invalid unicode escape '\\u{D800}'."]
+        );
+    }
+
+    fn types_of(source: &str) -> Vec<TokenType> {
+        tokens_of(source)
+            .into_iter()
+            .map(|(token_type, _, _)| token_type)
+            .collect()
+    }
+
+    #[test]
+    fn block_comments_are_skipped() {
+        assert_eq!(
+            types_of("1 /* two\n * lines */ + /**/ 2"),
+            [
+                TokenType::Number,
+                TokenType::Plus,
+                TokenType::Number,
+                TokenType::Eof
+            ]
+        );
+        assert_eq!(positions("/*\n*/ x")[0], ("x".to_owned(), 2, 4));
+    }
+
+    #[test]
+    fn unterminated_block_comment_is_an_error() {
+        let (tokens, errors) = Scanner::new("print 1; /* never\nclosed".to_owned()).scan_partial();
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Unterminated block comment.");
+        assert_eq!((errors[0].line, errors[0].column), (1, 10));
+        assert!(errors[0].unterminated);
+    }
+
+    #[test]
+    fn block_comments_nest() {
+        assert_eq!(
+            types_of("/* outer /* inner */ still comment */ 1 /*/**/*/"),
+            [TokenType::Number, TokenType::Eof]
+        );
+        let (_, errors) = Scanner::new("/* /* */ 1".to_owned()).scan_partial();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Unterminated block comment.");
+    }
+
+    #[test]
+    fn simple_escapes_decode() {
+        assert_eq!(
+            tokens_of(r#""a\nb\tc\rd\\e\"f""#),
+            [string(r#""a\nb\tc\rd\\e\"f""#, "a\nb\tc\rd\\e\"f"), eof()]
+        );
+    }
+
+    #[test]
+    fn unknown_escape_is_an_error() {
+        assert_eq!(errors_of(r#""\q""#), ["Unknown escape sequence '\\q'."]);
+        let (tokens, _) = Scanner::new(r#"print "a\qb";"#.to_owned()).scan_partial();
+        assert_eq!(tokens[1].literal, Some(Literal::String("ab".to_owned())));
+    }
+
+    #[test]
+    fn unicode_escapes_need_braces_and_a_scalar_value() {
+        assert_eq!(
+            tokens_of(r#""\u{e9}\u{10FFFF}""#),
+            [string(r#""\u{e9}\u{10FFFF}""#, "\u{e9}\u{10FFFF}"), eof()]
+        );
+        assert_eq!(errors_of(r#""\u41""#), ["Expect '{' after '\\u'."]);
+        assert_eq!(
+            errors_of(r#""\u{41""#),
+            ["Expect '}' after unicode escape digits."]
+        );
+        assert_eq!(
+            errors_of(r#""\u{110000}""#),
+            ["Invalid unicode escape '\\u{110000}'."]
+        );
+        assert_eq!(
+            errors_of(r#""\u{0000041}""#),
+            ["Invalid unicode escape '\\u{0000041}'."]
+        );
+    }
+
+    #[test]
+    fn malformed_radix_literals_are_errors() {
+        assert_eq!(errors_of("0x;"), ["Expect digits after '0x'."]);
+        assert_eq!(errors_of("0b;"), ["Expect digits after '0b'."]);
+        assert_eq!(
+            errors_of("0x1_0000_0000_0000_0000;"),
+            ["Number literal '0x10000000000000000' is too large."]
+        );
+        assert_eq!(
+            tokens_of("0xFFFF_FFFF_FFFF_FFFF")[0].2,
+            Some(Literal::Number(1.8446744073709552e19))
+        );
+    }
+
+    #[test]
+    fn malformed_number_is_one_token() {
+        let cases = vec!["5__0", "1_", "1_.5", "1.5_", "0x1_", "0b1__0"];
+        for source in cases {
+            let (tokens, errors) = Scanner::new(format!("print {};", source)).scan_partial();
+            assert_eq!(errors.len(), 1, "scanning {}", source);
+            let types: Vec<TokenType> = tokens.into_iter().map(|token| token.token_type).collect();
+            assert_eq!(
+                types,
+                [
+                    TokenType::Print,
+                    TokenType::Number,
+                    TokenType::Semicolon,
+                    TokenType::Eof
+                ],
+                "scanning {}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn scans_a_large_source_in_linear_time() {
+        let source = "var x = \"héllo\"; // ünïcode\n".repeat(50_000);
+        let start = std::time::Instant::now();
+        let (tokens, errors) = Scanner::new(source).scan_partial();
+        assert!(errors.is_empty());
+        assert_eq!(tokens.len(), 5 * 50_000 + 1);
+        assert_eq!(tokens[tokens.len() - 2].line, 50_000);
+        // Re-walking the string for each character takes minutes here.
+        assert!(start.elapsed().as_secs() < 10, "took {:?}", start.elapsed());
+    }
+
+    #[test]
+    fn identifiers_may_contain_underscores_after_the_first_letter() {
+        assert_eq!(
+            tokens_of("random_int a__b c_ or_x")
+                .into_iter()
+                .map(|(token_type, lexeme, _)| (token_type, lexeme))
+                .collect::<Vec<_>>(),
+            vec![
+                (TokenType::Identifier, "random_int".to_owned()),
+                (TokenType::Identifier, "a__b".to_owned()),
+                (TokenType::Identifier, "c_".to_owned()),
+                (TokenType::Identifier, "or_x".to_owned()),
+                (TokenType::Eof, "".to_owned()),
+            ]
+        );
+        assert_eq!(errors_of("_5"), ["Unexpected character"]);
+        assert_eq!(errors_of("_name"), ["Unexpected character"]);
+    }
+
+    #[test]
+    fn compound_assignment_operators_are_single_tokens() {
+        assert_eq!(
+            types_of("+= -= *= /= + ="),
+            vec![
+                TokenType::PlusEqual,
+                TokenType::MinusEqual,
+                TokenType::StarEqual,
+                TokenType::SlashEqual,
+                TokenType::Plus,
+                TokenType::Equal,
+                TokenType::Eof,
+            ]
+        );
     }
 }
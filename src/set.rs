@@ -0,0 +1,126 @@
+use crate::token::{Key, Literal};
+use std::collections::HashSet;
+
+// The runtime value behind `set(...)`. Members are unique by `Key` equality,
+// so lists and instances are members by identity, and are kept in the order
+// they were first added so printing a set is deterministic.
+#[derive(Debug, Default)]
+pub struct LoxSet {
+    members: Vec<Literal>,
+    keys: HashSet<Key>,
+}
+
+impl LoxSet {
+    pub fn new() -> Self {
+        LoxSet::default()
+    }
+
+    // Builds a set of the distinct `values`, in order, failing if any is NaN.
+    pub fn from_values<I: IntoIterator<Item = Literal>>(values: I) -> Result<Self, String> {
+        let mut set = LoxSet::new();
+        for value in values {
+            set.insert(value)?;
+        }
+        Ok(set)
+    }
+
+    // Returns whether `value` wasn't already a member. NaN can't be a member,
+    // since it's not equal to itself.
+    pub fn insert(&mut self, value: Literal) -> Result<bool, String> {
+        let key = match Key::new(value.clone()) {
+            Some(key) => key,
+            None => return Err("Sets can't contain NaN.".to_owned()),
+        };
+        let added = self.keys.insert(key);
+        if added {
+            self.members.push(value);
+        }
+        Ok(added)
+    }
+
+    pub fn contains(&self, value: &Literal) -> bool {
+        match Key::new(value.clone()) {
+            Some(key) => self.keys.contains(&key),
+            None => false,
+        }
+    }
+
+    // Returns whether `value` was a member.
+    pub fn remove(&mut self, value: &Literal) -> bool {
+        let key = match Key::new(value.clone()) {
+            Some(key) => key,
+            None => return false,
+        };
+        let removed = self.keys.remove(&key);
+        if removed {
+            self.members
+                .retain(|member| Key::new(member.clone()).as_ref() != Some(&key));
+        }
+        removed
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Literal> {
+        self.members.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_first_of_each_member_in_order() {
+        let set = LoxSet::from_values(
+            vec![3.0, 1.0, 3.0, 2.0, 1.0]
+                .into_iter()
+                .map(Literal::Number),
+        )
+        .unwrap();
+        let members: Vec<&Literal> = set.iter().collect();
+        assert_eq!(
+            members,
+            [
+                &Literal::Number(3.0),
+                &Literal::Number(1.0),
+                &Literal::Number(2.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn removing_keeps_the_order_of_the_rest() {
+        let mut set = LoxSet::from_values(
+            vec!["a", "b", "c"]
+                .into_iter()
+                .map(|s| Literal::String(s.to_owned())),
+        )
+        .unwrap();
+        assert!(set.remove(&Literal::String("b".to_owned())));
+        assert!(!set.remove(&Literal::String("b".to_owned())));
+        assert!(!set.contains(&Literal::String("b".to_owned())));
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.iter().last(), Some(&Literal::String("c".to_owned())));
+    }
+
+    #[test]
+    fn nan_is_never_a_member() {
+        let nan = Literal::Number(f64::NAN);
+        let mut set = LoxSet::new();
+        assert_eq!(
+            set.insert(nan.clone()),
+            Err("Sets can't contain NaN.".to_owned())
+        );
+        assert!(set.is_empty());
+        assert!(!set.contains(&nan));
+        assert!(!set.remove(&nan));
+        assert!(LoxSet::from_values(vec![Literal::Number(1.0), nan]).is_err());
+    }
+}
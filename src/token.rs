@@ -1,16 +1,28 @@
+use crate::callable::Callable;
+use crate::class::{LoxClass, LoxInstance};
+use crate::set::LoxSet;
+use std::cell::RefCell;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::rc::Rc;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TokenType {
     // Single-character tokens.
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
+    Percent,
     Plus,
+    Question,
     Semicolon,
     Slash,
     Star,
@@ -24,6 +36,10 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    MinusEqual,
+    PlusEqual,
+    SlashEqual,
+    StarEqual,
 
     // Literals.
     Identifier,
@@ -32,12 +48,15 @@ pub enum TokenType {
 
     // Keywords.
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
     For,
     If,
+    In,
     Nil,
     Or,
     Print,
@@ -51,12 +70,13 @@ pub enum TokenType {
     Eof,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
     pub literal: Option<Literal>,
     pub line: i32,
+    pub column: i32,
 }
 
 impl fmt::Display for Token {
@@ -66,20 +86,332 @@ impl fmt::Display for Token {
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, lexeme: String, literal: Option<Literal>, line: i32) -> Self {
+    pub fn new(
+        token_type: TokenType,
+        lexeme: String,
+        literal: Option<Literal>,
+        line: i32,
+        column: i32,
+    ) -> Self {
         Token {
             token_type,
             lexeme,
             literal,
             line,
+            column,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Clone)]
 pub enum Literal {
     Bool(bool),
     Number(f64),
     String(String),
     Nil,
+    Callable(Rc<dyn Callable>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<Literal>>>),
+    Set(Rc<RefCell<LoxSet>>),
+    Bytes(Rc<Vec<u8>>),
+}
+
+// Lists and sets can contain themselves, so formatting tracks the ones it's
+// inside and writes `[...]` or `{...}` on meeting one again, as `print` does.
+impl fmt::Debug for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let visiting = RefCell::new(Vec::new());
+        DebugLiteral {
+            value: self,
+            visiting: &visiting,
+        }
+        .fmt(f)
+    }
+}
+
+struct DebugLiteral<'a> {
+    value: &'a Literal,
+    visiting: &'a RefCell<Vec<*const ()>>,
+}
+
+impl DebugLiteral<'_> {
+    fn enter(&self, id: *const ()) -> bool {
+        let mut visiting = self.visiting.borrow_mut();
+        if visiting.contains(&id) {
+            return false;
+        }
+        visiting.push(id);
+        true
+    }
+
+    fn elements<'a>(&'a self, values: &'a [Literal]) -> Vec<DebugLiteral<'a>> {
+        values
+            .iter()
+            .map(|value| DebugLiteral {
+                value,
+                visiting: self.visiting,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for DebugLiteral<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.value {
+            Literal::Bool(b) => f.debug_tuple("Bool").field(b).finish(),
+            Literal::Number(n) => f.debug_tuple("Number").field(n).finish(),
+            Literal::String(s) => f.debug_tuple("String").field(s).finish(),
+            Literal::Nil => write!(f, "Nil"),
+            Literal::Callable(c) => write!(f, "Callable({})", c),
+            Literal::Class(c) => write!(f, "Class({})", c),
+            Literal::Instance(i) => write!(f, "Instance({})", i.borrow()),
+            Literal::List(l) => {
+                if !self.enter(Rc::as_ptr(l) as *const ()) {
+                    return write!(f, "List([...])");
+                }
+                let list = l.borrow();
+                let result = f.debug_tuple("List").field(&self.elements(&list)).finish();
+                self.visiting.borrow_mut().pop();
+                result
+            }
+            Literal::Set(s) => {
+                if !self.enter(Rc::as_ptr(s) as *const ()) {
+                    return write!(f, "{{...}}");
+                }
+                let members: Vec<Literal> = s.borrow().iter().cloned().collect();
+                let result = f.debug_set().entries(self.elements(&members)).finish();
+                self.visiting.borrow_mut().pop();
+                result
+            }
+            Literal::Bytes(b) => f.debug_tuple("Bytes").field(b).finish(),
+        }
+    }
+}
+
+// Callables, classes, instances, lists and sets have identity rather than structure: two
+// such values are only equal if they are the same object. Bytes can't be
+// changed once made, so like strings they compare by content.
+impl PartialEq for Literal {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Literal::Bool(a), Literal::Bool(b)) => a == b,
+            (Literal::Number(a), Literal::Number(b)) => a == b,
+            (Literal::String(a), Literal::String(b)) => a == b,
+            (Literal::Nil, Literal::Nil) => true,
+            (Literal::Callable(a), Literal::Callable(b)) => Rc::ptr_eq(a, b),
+            (Literal::Class(a), Literal::Class(b)) => Rc::ptr_eq(a, b),
+            (Literal::Instance(a), Literal::Instance(b)) => Rc::ptr_eq(a, b),
+            (Literal::List(a), Literal::List(b)) => Rc::ptr_eq(a, b),
+            (Literal::Set(a), Literal::Set(b)) => Rc::ptr_eq(a, b),
+            (Literal::Bytes(a), Literal::Bytes(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+// A runtime value as a map key or set element. Keys compare like `==`, so
+// NaN, which isn't equal to itself, can't be one: it could never be found
+// again once stored. Numbers hash by their bit pattern, with `-0.0` folded
+// into `0.0`.
+#[derive(Debug, Clone)]
+pub struct Key(Literal);
+
+impl Key {
+    // None if `value` is NaN.
+    pub fn new(value: Literal) -> Option<Key> {
+        match value {
+            Literal::Number(n) if n.is_nan() => None,
+            value => Some(Key(value)),
+        }
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.key_eq(&other.0)
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        debug_assert!(
+            !matches!(self.0, Literal::Number(n) if n.is_nan()),
+            "NaN key"
+        );
+        self.0.key_hash(state)
+    }
+}
+
+// Tokens can carry a NaN literal (a malformed number literal is scanned as
+// one), so here NaN does match NaN and every NaN hashes alike, keeping a token
+// equal to itself.
+impl Literal {
+    fn key_eq(&self, other: &Literal) -> bool {
+        match (self, other) {
+            (Literal::Number(a), Literal::Number(b)) => a == b || (a.is_nan() && b.is_nan()),
+            _ => self == other,
+        }
+    }
+
+    fn key_hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Literal::Bool(b) => b.hash(state),
+            Literal::Number(n) => {
+                let n = if *n == 0.0 {
+                    0.0
+                } else if n.is_nan() {
+                    f64::NAN
+                } else {
+                    *n
+                };
+                n.to_bits().hash(state)
+            }
+            Literal::String(s) => s.hash(state),
+            Literal::Nil => {}
+            Literal::Callable(c) => (Rc::as_ptr(c) as *const () as usize).hash(state),
+            Literal::Class(c) => (Rc::as_ptr(c) as usize).hash(state),
+            Literal::Instance(i) => (Rc::as_ptr(i) as usize).hash(state),
+            Literal::List(l) => (Rc::as_ptr(l) as usize).hash(state),
+            Literal::Set(s) => (Rc::as_ptr(s) as usize).hash(state),
+            Literal::Bytes(b) => b.hash(state),
+        }
+    }
+}
+
+// A token's identity is its type, lexeme and literal, not where it appears,
+// so the same name used on two lines is one key. Literals compare with
+// `key_eq`, so a token is always equal to itself.
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.token_type == other.token_type
+            && self.lexeme == other.lexeme
+            && match (&self.literal, &other.literal) {
+                (Some(a), Some(b)) => a.key_eq(b),
+                (None, None) => true,
+                _ => false,
+            }
+    }
+}
+
+impl Eq for Token {}
+
+impl Hash for Token {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.token_type.hash(state);
+        self.lexeme.hash(state);
+        if let Some(literal) = &self.literal {
+            literal.key_hash(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+
+    fn keys(values: Vec<Literal>) -> HashSet<Key> {
+        values
+            .into_iter()
+            .map(|value| Key::new(value).expect("value should be a key"))
+            .collect()
+    }
+
+    #[test]
+    fn debug_formats_self_referential_collections() {
+        let list = Rc::new(RefCell::new(vec![Literal::Number(1.0)]));
+        list.borrow_mut().push(Literal::List(Rc::clone(&list)));
+        let value = Literal::List(Rc::clone(&list));
+        assert_eq!(format!("{:?}", value), "List([Number(1.0), List([...])])");
+
+        let set = Rc::new(RefCell::new(LoxSet::new()));
+        let inner = Rc::new(RefCell::new(vec![Literal::Set(Rc::clone(&set))]));
+        set.borrow_mut().insert(Literal::List(inner)).unwrap();
+        assert_eq!(
+            format!("{:?}", Literal::Set(Rc::clone(&set))),
+            "{List([{...}])}"
+        );
+        // Break the cycles so the test doesn't leak.
+        list.borrow_mut().clear();
+        *set.borrow_mut() = LoxSet::new();
+    }
+
+    #[test]
+    fn tokens_are_keyed_by_name_not_position() {
+        let mut uses: HashMap<Token, usize> = HashMap::new();
+        for (line, column) in &[(1, 5), (3, 1), (7, 12)] {
+            let token = Token::new(
+                TokenType::Identifier,
+                "count".to_owned(),
+                None,
+                *line,
+                *column,
+            );
+            *uses.entry(token).or_insert(0) += 1;
+        }
+        let other = Token::new(TokenType::Identifier, "total".to_owned(), None, 1, 5);
+        uses.insert(other, 1);
+        assert_eq!(uses.len(), 2);
+        let lookup = Token::new(TokenType::Identifier, "count".to_owned(), None, 99, 1);
+        assert_eq!(uses.get(&lookup), Some(&3));
+    }
+
+    #[test]
+    fn tokens_with_different_literals_differ() {
+        let number = |n| {
+            Token::new(
+                TokenType::Number,
+                "1".to_owned(),
+                Some(Literal::Number(n)),
+                1,
+                1,
+            )
+        };
+        assert_eq!(number(1.0), number(1.0));
+        assert_ne!(number(1.0), number(2.0));
+        assert_eq!(number(f64::NAN), number(f64::NAN));
+    }
+
+    #[test]
+    fn primitive_keys_dedup() {
+        let set = keys(vec![
+            Literal::Number(1.0),
+            Literal::Number(1.0),
+            Literal::String("a".to_owned()),
+            Literal::String("a".to_owned()),
+            Literal::Bool(true),
+            Literal::Bool(true),
+            Literal::Nil,
+            Literal::Nil,
+        ]);
+        assert_eq!(set.len(), 4);
+        assert!(set.contains(&Key(Literal::String("a".to_owned()))));
+        assert!(!set.contains(&Key(Literal::Bool(false))));
+    }
+
+    #[test]
+    fn zeroes_are_one_key() {
+        let set = keys(vec![Literal::Number(0.0), Literal::Number(-0.0)]);
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn nan_is_not_a_key() {
+        assert!(Key::new(Literal::Number(f64::NAN)).is_none());
+        assert!(Key::new(Literal::Number(-f64::NAN)).is_none());
+        assert!(Key::new(Literal::Number(f64::INFINITY)).is_some());
+    }
+
+    #[test]
+    fn lists_are_keyed_by_identity() {
+        let list = Literal::List(Rc::new(RefCell::new(vec![])));
+        let other = Literal::List(Rc::new(RefCell::new(vec![])));
+        let set = keys(vec![list.clone(), list.clone(), other]);
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&Key(list)));
+    }
 }
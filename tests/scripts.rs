@@ -0,0 +1,1064 @@
+// Whole programs run through `Lox::run_source`, the embedding entry point.
+use rlox::interpreter::Interpreter;
+use rlox::token::Literal;
+use rlox::{Lox, LoxError};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::thread;
+
+// A `Write` the test keeps a handle to after giving it to the interpreter.
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Output {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+// Runs `source`, returning what it printed, or the messages of the errors
+// that stopped it.
+fn run(source: &str) -> Result<String, Vec<String>> {
+    execute(source, |_| {}, false)
+}
+
+// Runs `source` on both the tree-walker and the VM, with the interpreter set
+// up by `configure`, and checks they agree.
+fn run_both(source: &str, configure: fn(&mut Interpreter)) -> Result<String, Vec<String>> {
+    let tree_walker = execute(source, configure, false);
+    assert_eq!(tree_walker, execute(source, configure, true), "{}", source);
+    tree_walker
+}
+
+fn execute(source: &str, configure: fn(&mut Interpreter), vm: bool) -> Result<String, Vec<String>> {
+    let output = Output::default();
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+    configure(&mut interpreter);
+    let mut lox = Lox::with_interpreter(interpreter);
+    lox.set_vm(vm);
+    lox.run_source(source)
+        .map_err(|errors| errors.into_iter().map(message).collect::<Vec<String>>())?;
+    Ok(output.text())
+}
+
+fn message(error: LoxError) -> String {
+    match error {
+        LoxError::Scan(error) => error.message,
+        LoxError::Parse(error) => error.message,
+        LoxError::Runtime(error) => error.message,
+    }
+}
+
+#[test]
+fn runs_a_program_after_a_byte_order_mark() {
+    assert_eq!(
+        run("\u{FEFF}var a = 1;\nprint a + 1;"),
+        Ok("2\n".to_owned())
+    );
+}
+
+#[test]
+fn this_outside_a_class_is_a_compile_error() {
+    let error = Err(vec!["Cannot use 'this' outside of a class.".to_owned()]);
+    assert_eq!(run("print this;"), error);
+    assert_eq!(run("fun f() { return this; }"), error);
+    assert_eq!(
+        run("class A { m() { fun f() { return this; } return f(); } } print A().m();"),
+        Ok("A instance\n".to_owned())
+    );
+}
+
+#[test]
+fn arity_errors_name_the_function() {
+    let declare = "fun add(a, b) { return a + b; } class Point {}";
+    let cases = [
+        ("add(1);", "Expected 2 arguments to 'add' but got 1."),
+        ("add(1, 2, 3);", "Expected 2 arguments to 'add' but got 3."),
+        ("sqrt();", "Expected 1 arguments to 'sqrt' but got 0."),
+        ("sqrt(1, 2);", "Expected 1 arguments to 'sqrt' but got 2."),
+        (
+            "range();",
+            "Expected 1 to 3 arguments to 'range' but got 0.",
+        ),
+        ("Point(1);", "Expected 0 arguments to 'Point' but got 1."),
+    ];
+    for (call, message) in &cases {
+        let source = format!("{} {}", declare, call);
+        assert_eq!(run(&source), Err(vec![message.to_string()]), "{}", call);
+    }
+}
+
+#[test]
+fn memoized_functions_run_once_per_argument_list() {
+    let source = "
+        var calls = 0;
+        fun square(n) { calls = calls + 1; return n * n; }
+        var fast = memoize(square);
+        print fast(3);
+        print fast(3);
+        print fast(4);
+        print calls;
+        clearCache(fast);
+        fast(3);
+        print calls;
+        print fast;
+    ";
+    assert_eq!(
+        run(source),
+        Ok("9\n9\n16\n2\n3\n<memoized fn square>\n".to_owned())
+    );
+}
+
+#[test]
+fn memoized_recursion_goes_through_the_cache() {
+    let source = "
+        var calls = 0;
+        fun fib(n) { calls = calls + 1; if (n < 2) return n; return fib(n - 1) + fib(n - 2); }
+        fib = memoize(fib);
+        print fib(25);
+        print calls;
+    ";
+    assert_eq!(run(source), Ok("75025\n26\n".to_owned()));
+}
+
+#[test]
+fn memoized_list_arguments_key_by_identity() {
+    let source = "
+        var calls = 0;
+        fun size(l) { calls = calls + 1; return len(l); }
+        var fast = memoize(size);
+        var a = range(3);
+        fast(a);
+        fast(a);
+        fast(range(3));
+        print calls;
+    ";
+    assert_eq!(run(source), Ok("2\n".to_owned()));
+}
+
+#[test]
+fn self_tail_calls_run_in_constant_stack() {
+    let source = "
+        fun countdown(n) {
+            if (n == 0) return \"done\";
+            return countdown(n - 1);
+        }
+        print countdown(1000000);
+    ";
+    assert_eq!(run(source), Ok("done\n".to_owned()));
+}
+
+#[test]
+fn tail_calls_get_a_fresh_scope_each_time() {
+    let source = "
+        fun collect(n, acc) {
+            var seen = n;
+            fun get() { return seen; }
+            if (n == 0) return acc + str(get());
+            return collect(n - 1, acc + str(get()) + \",\");
+        }
+        print collect(3, \"\");
+    ";
+    assert_eq!(run(source), Ok("3,2,1,0\n".to_owned()));
+}
+
+#[test]
+fn tail_calls_check_arity() {
+    assert_eq!(
+        run("fun wrong(a) { return wrong(a, a); } wrong(1);"),
+        Err(vec!["Expected 1 arguments to 'wrong' but got 2.".to_owned()])
+    );
+}
+
+#[test]
+fn vm_matches_the_tree_walker() {
+    let programs = vec![
+        "print (1 + 2) * -3;",
+        "var a = \"x\"; { var a = a + \"y\"; print a; } print a;",
+        "var i = 0; while (i < 3) { print i; i = i + 1; }",
+        "for (var i = 0; i < 3; i = i + 1) print i * i;",
+        "if (nil) print 1; else print 2; print nil or \"b\"; print 0 and false;",
+        "var t = true; print t ? 1 : 2;",
+        "print 1 + nil;",
+        "print undefined;",
+    ];
+    for program in programs {
+        run_both(program, |_| {}).ok();
+    }
+}
+
+#[test]
+fn vm_enforces_strict_conditions() {
+    let error = Err(vec!["Condition must be a boolean.".to_owned()]);
+    let strict = |interpreter: &mut Interpreter| interpreter.set_strict(true);
+    assert_eq!(run_both("if (1) print 1;", strict), error);
+    assert_eq!(run_both("while (nil) print 1;", strict), error);
+    assert_eq!(
+        run_both("if (1 < 2) print 1;", strict),
+        Ok("1\n".to_owned())
+    );
+}
+
+#[test]
+fn vm_enforces_the_statement_budget() {
+    let budget = |interpreter: &mut Interpreter| interpreter.set_max_statements(Some(100));
+    assert_eq!(
+        run_both("while (true) {}", budget),
+        Err(vec!["Execution budget exceeded.".to_owned()])
+    );
+    assert_eq!(
+        run_both("var i = 0; while (i < 10) i = i + 1; print i;", budget),
+        Ok("10\n".to_owned())
+    );
+}
+
+#[test]
+fn vm_echoes_expression_statements() {
+    let echo = |interpreter: &mut Interpreter| interpreter.set_echo(true);
+    assert_eq!(
+        run_both("var a = 1; a + 2; var s = \"hi\"; s;", echo),
+        Ok("3\n\"hi\"\n".to_owned())
+    );
+}
+
+#[test]
+fn closures_share_a_captured_variable() {
+    assert_eq!(
+        run("var increment; var peek;
+             {
+               var count = 0;
+               fun inc() { count = count + 1; }
+               fun get() { return count; }
+               increment = inc;
+               peek = get;
+             }
+             increment(); increment();
+             print peek();"),
+        Ok("2\n".to_owned())
+    );
+}
+
+#[test]
+fn profile_returns_the_result_and_reports_the_time() {
+    let output = Output::default();
+    let diagnostics = Output::default();
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+    interpreter.set_diagnostics(Box::new(diagnostics.clone()));
+    let mut lox = Lox::with_interpreter(interpreter);
+    lox.run_source("fun work() { return 6 * 7; } print profile(work);")
+        .unwrap();
+    assert_eq!(output.text(), "42\n");
+    let timing = diagnostics.text();
+    assert!(timing.starts_with("[profile] work: "), "{}", timing);
+    assert!(timing.ends_with("ms\n"), "{}", timing);
+
+    let errors = lox.run_source("fun fail() { return -\"a\"; } profile(fail);");
+    assert_eq!(
+        errors
+            .unwrap_err()
+            .into_iter()
+            .map(message)
+            .collect::<Vec<_>>(),
+        ["Invalid negation operand."]
+    );
+    assert_eq!(diagnostics.text().lines().count(), 2);
+}
+
+#[test]
+fn profile_takes_a_function_of_no_arguments() {
+    assert_eq!(
+        run("fun f(x) {} profile(f);"),
+        Err(vec!["Expected 1 arguments to 'f' but got 0.".to_owned()])
+    );
+}
+
+#[test]
+fn bytes_round_trip_through_base64() {
+    assert_eq!(
+        run("var data = base64Decode(base64Encode(bytes(\"hi!\")));
+             print typeof(data); print data; print len(data);
+             print fromBytes(data);
+             print data == bytes(\"hi!\");"),
+        Ok("bytes\n<bytes 3>\n3\nhi!\ntrue\n".to_owned())
+    );
+    assert_eq!(
+        run("base64Decode(\"not base64\");"),
+        Err(vec!["Invalid base64 input.".to_owned()])
+    );
+}
+
+#[test]
+fn a_set_containing_itself_prints() {
+    assert_eq!(
+        run("var s = set(); add(s, 1); add(s, s); print s; print str(s);"),
+        Ok("{1, {...}}\n{1, {...}}\n".to_owned())
+    );
+    let output = Output::default();
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+    interpreter.set_echo(true);
+    Lox::with_interpreter(interpreter)
+        .run_source("var s = set(); add(s, s); s;")
+        .unwrap();
+    assert_eq!(output.text(), "nil\n{{...}}\n");
+}
+
+#[test]
+fn hosts_seed_globals_for_scripts() {
+    let output = Output::default();
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+    let data = vec![
+        Literal::Number(1.0),
+        Literal::Number(2.5),
+        Literal::Number(3.5),
+    ];
+    interpreter.set_global("data", Literal::List(Rc::new(RefCell::new(data))));
+    let mut lox = Lox::with_interpreter(interpreter);
+    lox.run_source("var total = 0; for (x in data) total = total + x; print total;")
+        .unwrap();
+    assert_eq!(output.text(), "7\n");
+}
+
+#[test]
+fn runaway_recursion_stops_at_the_default_depth() {
+    // Deeper than the default limit needs more than a test thread's stack.
+    let recursion = thread::Builder::new()
+        .stack_size(256 * 1024 * 1024)
+        .spawn(|| run("fun f(n) { return 1 + f(n + 1); } f(0);"))
+        .unwrap();
+    assert_eq!(
+        recursion.join().unwrap(),
+        Err(vec!["Stack overflow.".to_owned()])
+    );
+}
+
+#[test]
+fn a_malformed_number_is_reported_once() {
+    assert_eq!(
+        run("print 5__0 + 1;"),
+        Err(vec![
+            "Digit separator '_' must be between digits.".to_owned()
+        ])
+    );
+}
+
+#[test]
+fn errors_carry_line_and_column() {
+    let position = |source: &str| match Lox::new().run_source(source).unwrap_err().remove(0) {
+        LoxError::Scan(error) => (error.line, error.column),
+        LoxError::Parse(error) => (error.token.line, error.token.column),
+        LoxError::Runtime(error) => (error.token.line, error.token.column),
+    };
+    assert_eq!(position("var a = 1;\n  print a @;"), (2, 11));
+    assert_eq!(position("var a = 1;\nprint a +;"), (2, 10));
+    assert_eq!(position("var a = 1;\n\tprint a + nil;"), (2, 10));
+}
+
+#[test]
+fn modulo_binds_like_multiplication() {
+    assert_eq!(
+        run("var a = 7; print a % 3; print -a % 3; print 7.5 % 2; print 2 + a % 3 * 2;"),
+        Ok("1\n-1\n1.5\n4\n".to_owned())
+    );
+    assert_eq!(
+        run("var zero = 0; print 1 % zero;"),
+        Err(vec!["Modulo by zero.".to_owned()])
+    );
+    assert_eq!(
+        run("print \"a\" % 2;"),
+        Err(vec!["Operands must be numbers.".to_owned()])
+    );
+}
+
+#[test]
+fn boolean_literals_are_not_swapped() {
+    assert_eq!(
+        run("print true; print false; print !true;"),
+        Ok("true\nfalse\nfalse\n".to_owned())
+    );
+}
+
+#[test]
+fn calls_check_the_callee_and_argument_count() {
+    assert_eq!(
+        run("fun add(a, b) { print a + b; } add(1, 2);"),
+        Ok("3\n".to_owned())
+    );
+    assert_eq!(
+        run("var x = \"not a function\"; x();"),
+        Err(vec!["Can only call functions and classes.".to_owned()])
+    );
+    assert_eq!(
+        run("fun add(a, b) {} add(1);"),
+        Err(vec!["Expected 2 arguments to 'add' but got 1.".to_owned()])
+    );
+}
+
+#[test]
+fn return_exits_the_function_with_its_value() {
+    assert_eq!(
+        run("
+            fun sign(n) {
+                if (n < 0) return -1;
+                while (true) { if (n > 0) return 1; return 0; }
+                print \"unreachable\";
+            }
+            fun nothing() { return; }
+            fun fallsOff() {}
+            print sign(-5); print sign(5); print sign(0);
+            print nothing(); print fallsOff();
+        "),
+        Ok("-1\n1\n0\nnil\nnil\n".to_owned())
+    );
+    assert_eq!(
+        run("return 1;"),
+        Err(vec!["Can't return from top-level code.".to_owned()])
+    );
+}
+
+#[test]
+fn make_counter_returns_an_incrementing_closure() {
+    assert_eq!(
+        run("
+            fun makeCounter() {
+                var i = 0;
+                fun count() { i = i + 1; return i; }
+                return count;
+            }
+            var a = makeCounter();
+            var b = makeCounter();
+            print a(); print a(); print b(); print a();
+        "),
+        Ok("1\n2\n1\n3\n".to_owned())
+    );
+}
+
+#[test]
+fn clock_takes_no_arguments() {
+    assert_eq!(run("print clock() > 0;"), Ok("true\n".to_owned()));
+    assert_eq!(
+        run("clock(1);"),
+        Err(vec!["Expected 0 arguments to 'clock' but got 1.".to_owned()])
+    );
+}
+
+#[test]
+fn hosts_register_their_own_natives() {
+    let output = Output::default();
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+    interpreter.register_native("double", 1, |arguments| match arguments[0] {
+        Literal::Number(n) => Ok(Literal::Number(n * 2.0)),
+        _ => Err("Argument to 'double' must be a number.".to_owned()),
+    });
+    let mut lox = Lox::with_interpreter(interpreter);
+    lox.run_source("print double(21); print double;").unwrap();
+    assert_eq!(output.text(), "42\n<native fn double>\n");
+    let messages = |errors: Vec<LoxError>| errors.into_iter().map(message).collect::<Vec<_>>();
+    assert_eq!(
+        messages(lox.run_source("double(\"x\");").unwrap_err()),
+        ["Argument to 'double' must be a number."]
+    );
+    assert_eq!(
+        messages(lox.run_source("double(1, 2);").unwrap_err()),
+        ["Expected 1 arguments to 'double' but got 2."]
+    );
+}
+
+#[test]
+fn classes_construct_instances_with_methods() {
+    assert_eq!(
+        run("
+            class Greeter { greet(name) { print \"hi \" + name; } }
+            print Greeter;
+            var g = Greeter();
+            print g;
+            g.greet(\"bob\");
+            var greet = g.greet;
+            greet(\"al\");
+            print Greeter() == Greeter();
+        "),
+        Ok("Greeter\nGreeter instance\nhi bob\nhi al\nfalse\n".to_owned())
+    );
+    assert_eq!(
+        run("class A {} A(1);"),
+        Err(vec!["Expected 0 arguments to 'A' but got 1.".to_owned()])
+    );
+}
+
+#[test]
+fn properties_get_and_set_on_instances() {
+    assert_eq!(
+        run("
+            class P { describe() { return \"method\"; } }
+            var p = P();
+            p.x = 1;
+            p.x = p.x + 1;
+            print p.x;
+            print p.describe();
+            p.describe = \"field\";
+            print p.describe;
+        "),
+        Ok("2\nmethod\nfield\n".to_owned())
+    );
+    assert_eq!(
+        run("class P {} print P().missing;"),
+        Err(vec!["Undefined property 'missing'.".to_owned()])
+    );
+    assert_eq!(
+        run("var n = 1; print n.x;"),
+        Err(vec!["Only instances have properties.".to_owned()])
+    );
+    assert_eq!(
+        run("var n = 1; n.x = 2;"),
+        Err(vec!["Only instances have fields.".to_owned()])
+    );
+}
+
+#[test]
+fn this_is_the_instance_a_method_was_taken_from() {
+    assert_eq!(
+        run("
+            class Counter {
+                bump() { this.n = this.n + 1; return this; }
+                later() { fun get() { return this.n; } return get; }
+            }
+            var c = Counter();
+            c.n = 0;
+            c.bump().bump();
+            var bump = c.bump;
+            bump();
+            print c.n;
+            var get = c.later();
+            c.n = 10;
+            print get();
+        "),
+        Ok("3\n10\n".to_owned())
+    );
+}
+
+#[test]
+fn subclasses_inherit_override_and_call_super() {
+    assert_eq!(
+        run("
+            class A {
+                name() { return \"A\"; }
+                greet() { return \"I am \" + this.name(); }
+            }
+            class B < A { name() { return \"B<\" + super.name(); } }
+            class C < B { greet() { return super.greet() + \"!\"; } }
+            print B().greet();
+            print C().greet();
+        "),
+        Ok("I am B<A\nI am B<A!\n".to_owned())
+    );
+    assert_eq!(
+        run("var NotAClass = 1; class B < NotAClass {}"),
+        Err(vec!["Superclass must be a class.".to_owned()])
+    );
+    assert_eq!(
+        run("class A { m() { return super.m(); } }"),
+        Err(vec![
+            "Can't use 'super' in a class with no superclass.".to_owned()
+        ])
+    );
+    assert_eq!(
+        run("class A {} class B < A { m() { return super.missing(); } } B().m();"),
+        Err(vec!["Undefined property 'missing'.".to_owned()])
+    );
+}
+
+#[test]
+fn break_and_continue_control_the_innermost_loop() {
+    assert_eq!(
+        run("
+            for (var i = 0; i < 5; i = i + 1) {
+                if (i == 1) continue;
+                if (i == 3) break;
+                print i;
+            }
+            for (x in range(3)) for (y in range(3)) { if (y == 1) break; print x * 10 + y; }
+        "),
+        Ok("0\n2\n0\n10\n20\n".to_owned())
+    );
+    assert_eq!(
+        run("break;"),
+        Err(vec!["Can't use 'break' outside of a loop.".to_owned()])
+    );
+    assert_eq!(
+        run("while (true) { fun f() { continue; } }"),
+        Err(vec!["Can't use 'continue' outside of a loop.".to_owned()])
+    );
+}
+
+#[test]
+fn run_source_collects_errors_and_keeps_state() {
+    let mut lox = Lox::with_interpreter(Interpreter::with_output(Box::new(io::sink())));
+    let errors = lox.run_source("print 1 +;\nvar;\n\"open").unwrap_err();
+    assert!(matches!(errors[0], LoxError::Scan(_)));
+    assert!(errors[1..].iter().all(|e| matches!(e, LoxError::Parse(_))));
+    assert_eq!(errors.len(), 3);
+    lox.run_source("var kept = 1;").unwrap();
+    let errors = lox.run_source("print kept + nil;").unwrap_err();
+    assert!(matches!(errors[..], [LoxError::Runtime(_)]));
+    assert_eq!(
+        message(errors.into_iter().next().unwrap()),
+        "Operands must be either two numbers or two strings."
+    );
+}
+
+#[test]
+fn ternary_evaluates_only_the_chosen_branch() {
+    let source = "
+        fun say(s) { print s; return s; }
+        var a = true ? say(\"yes\") : say(\"no\");
+        var b = nil ? 1 : 0 ? \"zero\" : \"other\";
+        print b;
+    ";
+    assert_eq!(run(source), Ok("yes\nzero\n".to_owned()));
+    let source = "var n = 3; print n > 2 ? \"big\" : n > 1 ? \"medium\" : \"small\";";
+    assert_eq!(run_both(source, |_| {}), Ok("big\n".to_owned()));
+}
+
+#[test]
+fn scripts_never_echo_expression_results() {
+    assert_eq!(run("1 + 1;"), Ok(String::new()));
+    let source = "var a = 1; a = a + 1; a; fun f() { return 3; } f();";
+    assert_eq!(run(source), Ok(String::new()));
+}
+
+#[test]
+fn undefined_names_suggest_locals_in_scope() {
+    assert_eq!(
+        run("{ var count = 1; print cont; }"),
+        Err(vec![
+            "Undefined variable 'cont'. Did you mean 'count'?".to_owned()
+        ])
+    );
+    assert_eq!(
+        run("fun f(total) { totl = 2; } f(1);"),
+        Err(vec![
+            "Undefined variable 'totl'. Did you mean 'total'?".to_owned()
+        ])
+    );
+    assert_eq!(
+        run("{ var count = 1; } print cont;"),
+        Err(vec!["Undefined variable 'cont'.".to_owned()])
+    );
+}
+
+#[test]
+fn closures_keep_the_variable_they_were_written_against() {
+    let source = "
+        var a = \"global\";
+        {
+            fun show() { print a; }
+            show();
+            var a = \"block\";
+            show();
+            print a;
+        }
+    ";
+    assert_eq!(run(source), Ok("global\nglobal\nblock\n".to_owned()));
+}
+
+#[test]
+fn a_local_can_be_shadowed_but_not_redeclared() {
+    assert_eq!(
+        run("{ var a = 1; var a = 2; print a; }"),
+        Err(vec![
+            "Already a variable with this name in this scope.".to_owned()
+        ])
+    );
+    assert_eq!(
+        run("var a = 1; var a = 2; { var b = a; { var c = b + 1; var b = c; print b; } }"),
+        Ok("3\n".to_owned())
+    );
+}
+
+#[test]
+fn a_local_initializer_cannot_read_the_local() {
+    assert_eq!(
+        run("var a = 1; { var a = a + 1; print a; }"),
+        Err(vec![
+            "Can't read local variable in its own initializer.".to_owned()
+        ])
+    );
+    assert_eq!(
+        run("var a = 1; { var b = a + 1; print b; }"),
+        Ok("2\n".to_owned())
+    );
+}
+
+#[test]
+fn len_measures_strings_and_lists() {
+    assert_eq!(
+        run("print len(\"abc\"); print len(\"\"); print len(split(\"a,b\", \",\"));"),
+        Ok("3\n0\n2\n".to_owned())
+    );
+    assert_eq!(
+        run("print len(nil);"),
+        Err(vec![
+            "Argument to 'len' must be a string, a list or bytes.".to_owned()
+        ])
+    );
+}
+
+#[test]
+fn strings_index_by_character() {
+    assert_eq!(
+        run("var s = \"héllo\"; print s[1]; print s[4]; print substring(s, 1, 4);"),
+        Ok("é\no\néll\n".to_owned())
+    );
+    assert_eq!(
+        run("print \"abc\"[3];"),
+        Err(vec!["Index 3 is out of range.".to_owned()])
+    );
+    assert_eq!(
+        run("print \"abc\"[-1];"),
+        Err(vec!["Index -1 is out of range.".to_owned()])
+    );
+    assert_eq!(
+        run("print 12[0];"),
+        Err(vec!["Only strings and lists can be indexed.".to_owned()])
+    );
+}
+
+#[test]
+fn case_and_trim_natives_return_new_strings() {
+    assert_eq!(
+        run("var s = \"  Mixed Case \"; print upper(s); print lower(trim(s)); print s;"),
+        Ok("  MIXED CASE \nmixed case\n  Mixed Case \n".to_owned())
+    );
+    assert_eq!(
+        run("print upper(1);"),
+        Err(vec!["Argument to 'upper' must be a string.".to_owned()])
+    );
+}
+
+#[test]
+fn split_returns_a_list_of_pieces() {
+    let source = "
+        var words = split(\"one two three\", \" \");
+        print len(words);
+        print words[2];
+        print words;
+    ";
+    assert_eq!(
+        run(source),
+        Ok("3\nthree\n[\"one\", \"two\", \"three\"]\n".to_owned())
+    );
+}
+
+#[test]
+fn math_natives_compose() {
+    assert_eq!(
+        run("print sqrt(pow(3, 2) + pow(4, 2)); print floor(7 / 2); print ceil(abs(-0.5));"),
+        Ok("5\n3\n1\n".to_owned())
+    );
+    assert_eq!(
+        run("print sqrt(\"4\");"),
+        Err(vec!["Argument to 'sqrt' must be a number.".to_owned()])
+    );
+}
+
+#[test]
+fn seeding_makes_random_reproducible() {
+    let source = "
+        seed(7);
+        var a = random();
+        seed(7);
+        print a == random();
+        print a >= 0 and a < 1;
+    ";
+    assert_eq!(run(source), Ok("true\ntrue\n".to_owned()));
+    assert_eq!(
+        run("seed(\"x\");"),
+        Err(vec!["Argument to 'seed' must be a number.".to_owned()])
+    );
+}
+
+#[test]
+fn names_can_contain_underscores() {
+    let source = "
+        var max_roll = 6;
+        seed(1);
+        var roll = random_int(1, max_roll);
+        print roll >= 1 and roll <= max_roll;
+    ";
+    assert_eq!(run(source), Ok("true\n".to_owned()));
+    assert_eq!(
+        run("var _5 = 1;"),
+        Err(vec![
+            "Unexpected character".to_owned(),
+            "Expect variable name.".to_owned()
+        ])
+    );
+}
+
+#[test]
+fn input_reads_lines_from_the_hosts_reader() {
+    let output = Output::default();
+    let input = io::Cursor::new("Ada\n3\n");
+    let interpreter = Interpreter::with_io(Box::new(input), Box::new(output.clone()));
+    let mut lox = Lox::with_interpreter(interpreter);
+    let source = "
+        var name = input();
+        var times = num(input());
+        for (var i = 0; i < times; i = i + 1) print \"hi \" + name;
+        print input();
+    ";
+    assert!(lox.run_source(source).is_ok());
+    assert_eq!(output.text(), "hi Ada\nhi Ada\nhi Ada\nnil\n");
+}
+
+#[test]
+fn num_and_str_round_trip() {
+    assert_eq!(
+        run("print num(\"4\") + 1; print str(4) + \"1\"; print num(str(0.25)) == 0.25;"),
+        Ok("5\n41\ntrue\n".to_owned())
+    );
+    assert_eq!(
+        run("print num(\"four\");"),
+        Err(vec!["Can't convert 'four' to a number.".to_owned()])
+    );
+}
+
+#[test]
+fn typeof_names_runtime_types() {
+    let source = "
+        fun f() {}
+        class A {}
+        print typeof(1);
+        print typeof(\"s\");
+        print typeof(nil);
+        print typeof(f);
+        print typeof(clock);
+        print typeof(A);
+        print typeof(A());
+        print typeof(range(2));
+        print typeof(set());
+        print typeof(typeof(1));
+    ";
+    assert_eq!(
+        run(source),
+        Ok(
+            "number\nstring\nnil\nfunction\nfunction\nclass\ninstance\nlist\nset\nstring\n"
+                .to_owned()
+        )
+    );
+}
+
+#[test]
+fn assert_stops_the_script_with_its_message() {
+    assert_eq!(
+        run("assert(1 + 1 == 2); print \"ok\"; assert(false, \"math is broken\"); print \"unreachable\";"),
+        Err(vec!["math is broken".to_owned()])
+    );
+    assert_eq!(
+        run("assert(nil);"),
+        Err(vec!["Assertion failed.".to_owned()])
+    );
+    assert_eq!(
+        run("assert();"),
+        Err(vec![
+            "Expected 1 to 2 arguments to 'assert' but got 0.".to_owned()
+        ])
+    );
+}
+
+#[test]
+fn strings_repeat_with_star() {
+    assert_eq!(
+        run("var line = \"-\" * 5; print line; print 2 * \"ab\"; print len(\"x\" * 10000);"),
+        Ok("-----\nabab\n10000\n".to_owned())
+    );
+    assert_eq!(
+        run("print \"a\" * 1.5;"),
+        Err(vec![
+            "Repetition count must be a non-negative whole number.".to_owned()
+        ])
+    );
+    assert_eq!(
+        run("var n = pow(10, 12); print \"abc\" * n;"),
+        Err(vec!["Repeated string is too long.".to_owned()])
+    );
+}
+
+#[test]
+fn string_coercion_is_opt_in() {
+    let source = "var n = 3; print \"n = \" + n;";
+    assert_eq!(
+        run_both(source, |_| {}),
+        Err(vec![
+            "Operands must be either two numbers or two strings.".to_owned()
+        ])
+    );
+    assert_eq!(
+        run_both(source, |interpreter| interpreter.set_string_coercion(true)),
+        Ok("n = 3\n".to_owned())
+    );
+    assert_eq!(
+        run_both("print 1 - \"1\";", |interpreter| {
+            interpreter.set_string_coercion(true)
+        }),
+        Err(vec!["Operands must be numbers.".to_owned()])
+    );
+}
+
+#[test]
+fn strings_compare_lexicographically() {
+    let source = "
+        var a = \"abc\";
+        var b = \"abd\";
+        print a < b;
+        print b <= a;
+        print a > \"ab\";
+        print \"Z\" < \"a\";
+    ";
+    assert_eq!(
+        run_both(source, |_| {}),
+        Ok("true\nfalse\ntrue\ntrue\n".to_owned())
+    );
+    assert_eq!(
+        run_both("var n = 1; print n < \"2\";", |_| {}),
+        Err(vec![
+            "Operands must be two numbers or two strings.".to_owned()
+        ])
+    );
+}
+
+#[test]
+fn for_in_visits_each_element() {
+    let source = "
+        var total = 0;
+        for (n in range(1, 6)) {
+            if (n == 2) continue;
+            if (n == 5) break;
+            total = total + n;
+        }
+        print total;
+        var letter = \"outer\";
+        for (letter in split(\"ab\", \"\")) print letter;
+        print letter;
+    ";
+    assert_eq!(run(source), Ok("8\na\nb\nouter\n".to_owned()));
+    assert_eq!(
+        run("for (c in \"abc\") print c;"),
+        Err(vec!["Can only iterate over a list.".to_owned()])
+    );
+    assert_eq!(
+        execute("for (n in range(3)) print n;", |_| {}, true),
+        Err(vec!["Lists are not supported by the VM.".to_owned()])
+    );
+}
+
+#[test]
+fn range_builds_lists_of_numbers() {
+    let source = "
+        print range(4);
+        print range(2, 5);
+        print range(5, 0, -2);
+        print len(range(0, 1, 0.1));
+        print range(3, 3);
+    ";
+    assert_eq!(
+        run(source),
+        Ok("[0, 1, 2, 3]\n[2, 3, 4]\n[5, 3, 1]\n10\n[]\n".to_owned())
+    );
+    let cases = [
+        ("range(0, 10, 0);", "Step of 'range' can't be zero."),
+        ("range(pow(10, 15));", "Range is too long."),
+        (
+            "range(0, 1 / 3 * pow(10, 400));",
+            "Arguments to 'range' must be finite.",
+        ),
+        ("range(\"3\");", "Argument to 'range' must be a number."),
+    ];
+    for (call, message) in &cases {
+        assert_eq!(run(call), Err(vec![message.to_string()]), "{}", call);
+    }
+}
+
+#[test]
+fn compound_assignment_updates_in_place() {
+    let source = "
+        var x = 10;
+        x += 5;
+        print x;
+        x -= 3;
+        x *= 2;
+        print x;
+        x /= 8;
+        print x;
+        var s = \"ab\";
+        s += \"c\";
+        print s;
+        var a = 1;
+        var b = 1;
+        a += b += 2;
+        print a;
+        print b;
+    ";
+    assert_eq!(
+        run_both(source, |_| {}),
+        Ok("15\n24\n3\nabc\n4\n3\n".to_owned())
+    );
+    let source = "
+        class Counter {}
+        var c = Counter();
+        c.n = 1;
+        c.n += 41;
+        print c.n;
+    ";
+    assert_eq!(run(source), Ok("42\n".to_owned()));
+    assert_eq!(
+        run_both("var n = 1; n /= 0;", |_| {}),
+        Err(vec!["Division by zero.".to_owned()])
+    );
+}
+
+#[test]
+fn a_missing_left_operand_is_a_parse_error() {
+    assert_eq!(
+        run("var x = 1;\nprint * x;\nprint x == ;\nprint > 2;"),
+        Err(vec![
+            "Binary operator '*' has no left-hand operand.".to_owned(),
+            "Expect Expression".to_owned(),
+            "Binary operator '>' has no left-hand operand.".to_owned(),
+        ])
+    );
+}
+
+#[test]
+fn nan_cannot_be_a_set_member_or_memoized_argument() {
+    let nan = "var nan = pow(-1, 0.5);";
+    assert_eq!(
+        run(&format!("{} var s = set(); add(s, nan);", nan)),
+        Err(vec!["Sets can't contain NaN.".to_owned()])
+    );
+    assert_eq!(
+        run(&format!(
+            "{} var s = set(); print has(s, nan); print remove(s, nan);",
+            nan
+        )),
+        Ok("false\nfalse\n".to_owned())
+    );
+    assert_eq!(
+        run(&format!(
+            "{} fun id(x) {{ return x; }} memoize(id)(nan);",
+            nan
+        )),
+        Err(vec!["Can't memoize a call with a NaN argument.".to_owned()])
+    );
+}